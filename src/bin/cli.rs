@@ -8,17 +8,26 @@ use dialoguer::{Confirm, Input, Select};
 use lopdf::Document;
 
 use pdfsnap_server::{
+    env::get_fallback_font_paths,
     error::GenericError,
-    fonts::{CURSIVE_REGULAR_BYTES, FontType, FontWeight},
+    fonts::{FontType, FontWeight, load_fallback_fonts},
     pdf::{
-        ImageVariable, PdfVariable, TextAlignment, VerticalAlign, TextVariable, VariableOptions,
+        ImageVariable, MarkVariable, MarkdownVariable, PdfVariable, QrVariable, TextAlignment,
+        TextVariable, VariableOptions, VerticalAlign,
         acroform::remove_acroforms,
         color::Color,
-        font::{embed_ttf_font, get_most_used_font_size, reference_base_fonts},
-        get_page_media_box,
+        font::{FontRegistry, get_most_used_font_size},
+        get_page_size,
         image::{DrawImageOptions, draw_image},
+        layout::hyphenation_lang,
         link::{DrawLinkOptions, add_link},
-        text::{DrawTextOptions, draw_text, draw_text_fit, draw_text_wrap, draw_text_wrap_fit},
+        mark::{DrawMarkOptions, MarkStyle, draw_mark},
+        markdown::parse_markdown,
+        qr::{DEFAULT_QUIET_ZONE, DrawQrOptions, QrErrorCorrection, draw_qr},
+        text::{
+            DrawTextOptions, draw_markdown, draw_text, draw_text_fit, draw_text_lines_fit,
+            draw_text_wrap_fit,
+        },
     },
 };
 
@@ -26,6 +35,10 @@ use pdfsnap_server::{
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("=== PDFSnap CLI - Interactive PDF Generator ===\n");
 
+    // Only warnings, such as rejected fallback fonts, are shown unless RUST_LOG says otherwise
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+    load_fallback_fonts(&get_fallback_font_paths());
+
     // Get template path/URL
     let template_input: String = Input::new()
         .with_prompt("Enter PDF template path or URL")
@@ -45,7 +58,6 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     remove_acroforms(&mut document);
     let most_used_font_size = get_most_used_font_size(&document);
     let page_refs = document.get_pages();
-    reference_base_fonts(&mut document)?;
 
     println!("\nTemplate loaded successfully!");
     println!("Most used font size: {:.2}", most_used_font_size);
@@ -65,13 +77,21 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .interact_text()?;
 
     let mut variables = Vec::new();
-    let mut has_signature_embedded = false;
+    let mut fonts = FontRegistry::new();
 
     // Collect variables
     for i in 0..num_variables {
         println!("\n--- Variable {} ---", i + 1);
         
-        let var_types = vec!["text", "signature", "image"];
+        let var_types = vec![
+            "text",
+            "markdown",
+            "signature",
+            "image",
+            "checkbox",
+            "radio",
+            "qr",
+        ];
         let var_type_idx = Select::new()
             .with_prompt("Variable type")
             .items(&var_types)
@@ -82,20 +102,17 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
         // Get common properties
         let max_page = page_refs.len();
-        let page: usize = loop {
-            let input: usize = Input::new()
-                .with_prompt(format!("Page number (1-indexed, 1-{})", max_page))
-                .default(1)
-                .validate_with(|input: &usize| {
-                    if *input < 1 || *input > max_page {
-                        Err(format!("Page number must be between 1 and {}", max_page))
-                    } else {
-                        Ok(())
-                    }
-                })
-                .interact_text()?;
-            break input;
-        };
+        let page: usize = Input::new()
+            .with_prompt(format!("Page number (1-indexed, 1-{})", max_page))
+            .default(1)
+            .validate_with(|input: &usize| {
+                if *input < 1 || *input > max_page {
+                    Err(format!("Page number must be between 1 and {}", max_page))
+                } else {
+                    Ok(())
+                }
+            })
+            .interact_text()?;
 
         let x: f32 = Input::new()
             .with_prompt("X position")
@@ -117,6 +134,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             .default(50.0)
             .interact_text()?;
 
+        let rotation: f32 = Input::new()
+            .with_prompt("Rotation (degrees counterclockwise)")
+            .default(0.0)
+            .interact_text()?;
+        let rotation = (rotation != 0.0).then_some(rotation);
+
         let field: String = Input::new()
             .with_prompt("Field name (identifier)")
             .default(format!("field_{}", i + 1))
@@ -124,7 +147,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
         match var_type {
             "text" => {
-                let mut value: String = Input::new()
+                let value: String = Input::new()
                     .with_prompt("Text value")
                     .interact_text()?;
 
@@ -135,7 +158,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     .ok()
                     .and_then(|s: String| if s.is_empty() { None } else { s.parse().ok() });
 
-                let alignment_options = vec!["left", "center", "right"];
+                let alignment_options = vec!["left", "center", "right", "justify"];
                 let alignment_idx = Select::new()
                     .with_prompt("Text alignment")
                     .items(&alignment_options)
@@ -146,6 +169,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     "left" => Some(TextAlignment::Left),
                     "center" => Some(TextAlignment::Center),
                     "right" => Some(TextAlignment::Right),
+                    "justify" => Some(TextAlignment::Justify),
                     _ => None,
                 };
 
@@ -164,6 +188,53 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     _ => None,
                 };
 
+                let font_type_options = vec!["sans-serif", "serif", "mono"];
+                let font_type_idx = Select::new()
+                    .with_prompt("Font family")
+                    .items(&font_type_options)
+                    .default(0)
+                    .interact()?;
+
+                let font_type = match font_type_options[font_type_idx] {
+                    "serif" => Some(FontType::Serif),
+                    "mono" => Some(FontType::Mono),
+                    _ => Some(FontType::SansSerif),
+                };
+
+                let font_weight_options = vec!["regular", "light", "bold"];
+                let font_weight_idx = Select::new()
+                    .with_prompt("Font weight")
+                    .items(&font_weight_options)
+                    .default(0)
+                    .interact()?;
+
+                let font_weight = match font_weight_options[font_weight_idx] {
+                    "light" => Some(FontWeight::Light),
+                    "bold" => Some(FontWeight::Bold),
+                    _ => Some(FontWeight::Regular),
+                };
+
+                let italic: bool = Confirm::new()
+                    .with_prompt("Italic?")
+                    .default(false)
+                    .interact()?;
+
+                let hyphenation_input: String = Input::new()
+                    .with_prompt(
+                        "Hyphenation language (ISO 639-1 code, e.g. en, or press Enter for none)",
+                    )
+                    .allow_empty(true)
+                    .default("".to_string())
+                    .interact_text()?;
+
+                let hyphenation = match hyphenation_input.trim() {
+                    "" => None,
+                    code => {
+                        hyphenation_lang(&field, code)?;
+                        Some(code.to_string())
+                    }
+                };
+
                 let color_input: String = Input::new()
                     .with_prompt("Color (hex format, e.g., #000000 for black, or press Enter for default)")
                     .allow_empty(true)
@@ -181,22 +252,6 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     .default(true)
                     .interact()?;
 
-                if wrap_text {
-                    let char_limit_input: String = Input::new()
-                        .with_prompt("Maximum characters per line (press Enter to skip)")
-                        .allow_empty(true)
-                        .default("".to_string())
-                        .interact_text()?;
-
-                    if let Ok(limit) = char_limit_input.trim().parse::<usize>() {
-                        if limit > 0 {
-                            value = wrap_text_at_char_limit(&value, limit);
-                        }
-                    } else if !char_limit_input.trim().is_empty() {
-                        println!("⚠️ Could not parse character limit. Ignoring and using automatic wrapping.");
-                    }
-                }
-
                 variables.push(PdfVariable::Text(TextVariable {
                     variable: VariableOptions {
                         x,
@@ -206,12 +261,81 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                         page: page - 1, // Convert to 0-indexed
                         field,
                         value,
+                        rotation,
                     },
                     font_size,
                     align_h: text_alignment,
                     align_v: v_alignment,
                     color,
                     wrap: Some(wrap_text),
+                    font_type,
+                    font_weight,
+                    italic: Some(italic),
+                    hyphenation,
+                    line_height: None,
+                    letter_spacing: None,
+                    word_spacing: None,
+                    overflow: None,
+                    min_font_size: None,
+                    continuations: None,
+                    overflow_page: None,
+                    underline: None,
+                    strikethrough: None,
+                    background_color: None,
+                    border: None,
+                    markup: None,
+                    tab_stops: None,
+                    comb: None,
+                    font_features: None,
+                    render_mode: None,
+                    opacity: None,
+                }));
+            }
+            "markdown" => {
+                let value: String = Input::new()
+                    .with_prompt("Markdown value (type \\n for a new line)")
+                    .interact_text()?;
+                let value = value.replace("\\n", "\n");
+
+                let font_size: Option<f32> = Input::new()
+                    .with_prompt("Font size (press Enter for default)")
+                    .allow_empty(true)
+                    .interact_text()
+                    .ok()
+                    .and_then(|s: String| if s.is_empty() { None } else { s.parse().ok() });
+
+                variables.push(PdfVariable::Markdown(MarkdownVariable {
+                    variable: VariableOptions {
+                        x,
+                        y,
+                        w,
+                        h,
+                        page: page - 1,
+                        field,
+                        value,
+                        rotation,
+                    },
+                    font_size,
+                    align_h: None,
+                    align_v: None,
+                    color: None,
+                    font_type: None,
+                    font_weight: None,
+                    italic: None,
+                    hyphenation: None,
+                    line_height: None,
+                    letter_spacing: None,
+                    word_spacing: None,
+                    overflow: None,
+                    min_font_size: None,
+                    underline: None,
+                    strikethrough: None,
+                    background_color: None,
+                    border: None,
+                    tab_stops: None,
+                    font_features: None,
+                    render_mode: None,
+                    opacity: None,
                 }));
             }
             "signature" => {
@@ -249,12 +373,34 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                         page: page - 1,
                         field,
                         value,
+                        rotation,
                     },
                     font_size,
                     align_h: None,
                     align_v: v_alignment,
                     color: None,
                     wrap: None,
+                    font_type: None,
+                    font_weight: None,
+                    italic: None,
+                    hyphenation: None,
+                    line_height: None,
+                    letter_spacing: None,
+                    word_spacing: None,
+                    overflow: None,
+                    min_font_size: None,
+                    continuations: None,
+                    overflow_page: None,
+                    underline: None,
+                    strikethrough: None,
+                    background_color: None,
+                    border: None,
+                    markup: None,
+                    tab_stops: None,
+                    comb: None,
+                    font_features: None,
+                    render_mode: None,
+                    opacity: None,
                 }));
             }
             "image" => {
@@ -271,9 +417,92 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                         page: page - 1,
                         field,
                         value: image_url,
+                        rotation,
                     },
                 }));
             }
+            "checkbox" | "radio" => {
+                let checked: bool = Confirm::new()
+                    .with_prompt(if var_type == "radio" {
+                        "Selected?"
+                    } else {
+                        "Checked?"
+                    })
+                    .default(true)
+                    .interact()?;
+
+                let style_options = vec!["check", "cross", "circle", "square"];
+                let style_idx = Select::new()
+                    .with_prompt("Mark style")
+                    .items(&style_options)
+                    .default(if var_type == "radio" { 2 } else { 0 })
+                    .interact()?;
+
+                let style = match style_options[style_idx] {
+                    "cross" => MarkStyle::Cross,
+                    "circle" => MarkStyle::Circle,
+                    "square" => MarkStyle::Square,
+                    _ => MarkStyle::Check,
+                };
+
+                let mark = MarkVariable {
+                    variable: VariableOptions {
+                        x,
+                        y,
+                        w,
+                        h,
+                        page: page - 1,
+                        field,
+                        value: checked.to_string(),
+                        rotation,
+                    },
+                    style: Some(style),
+                    color: None,
+                    option: None,
+                };
+                variables.push(if var_type == "radio" {
+                    PdfVariable::Radio(mark)
+                } else {
+                    PdfVariable::Checkbox(mark)
+                });
+            }
+            "qr" => {
+                let value: String = Input::new()
+                    .with_prompt("QR code content (text or URL)")
+                    .interact_text()?;
+
+                let level_options = vec!["low", "medium", "quartile", "high"];
+                let level_idx = Select::new()
+                    .with_prompt("Error correction")
+                    .items(&level_options)
+                    .default(1)
+                    .interact()?;
+
+                let error_correction = match level_options[level_idx] {
+                    "low" => QrErrorCorrection::Low,
+                    "quartile" => QrErrorCorrection::Quartile,
+                    "high" => QrErrorCorrection::High,
+                    _ => QrErrorCorrection::Medium,
+                };
+
+                variables.push(PdfVariable::Qr(QrVariable {
+                    variable: VariableOptions {
+                        x,
+                        y,
+                        w,
+                        h,
+                        page: page - 1,
+                        field,
+                        value,
+                        rotation,
+                    },
+                    verification_url: None,
+                    error_correction: Some(error_correction),
+                    quiet_zone: None,
+                    color: None,
+                    background_color: None,
+                }));
+            }
             _ => {}
         }
     }
@@ -299,54 +528,54 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 // variable.variable.page is 0-indexed, convert to 1-indexed for lookup
                 let page_ref = get_page_ref(variable.variable.page + 1)?;
                 
-                let opts = DrawTextOptions {
-                    text: &variable.variable.value,
-                    font_weight: None,
-                    font_type: Some(FontType::SansSerif),
-                    font_size: variable.font_size.unwrap_or(most_used_font_size),
-                    color: variable.color,
-                    x: variable.variable.x,
-                    y: variable.variable.y,
-                    w: variable.variable.w,
-                    h: variable.variable.h,
-                    text_alignment: variable.align_h.clone(),
-                    align_v: variable.align_v.clone()
-                };
+                let rich_text = variable.rich_text()?;
+                let features = variable.features()?;
+                let opts = DrawTextOptions::from_variable(
+                    variable,
+                    &rich_text,
+                    &features,
+                    most_used_font_size,
+                )?;
 
                 if variable.wrap.unwrap_or(true) {
-                    draw_text_wrap_fit(&mut document, page_ref, opts)?;
+                    draw_text_wrap_fit(&mut document, &mut fonts, page_ref, opts)?;
                 } else {
-                    draw_text_fit(&mut document, page_ref, opts)?;
+                    draw_text_lines_fit(&mut document, &mut fonts, page_ref, opts)?;
                 }
             }
             PdfVariable::Signature(variable) => {
-                if !has_signature_embedded {
-                    embed_ttf_font(
-                        &mut document,
-                        "ABCDEE+ItalianoRegular",
-                        "pdf-Cursive",
-                        CURSIVE_REGULAR_BYTES,
-                    )?;
-                    has_signature_embedded = true;
-                }
-
                 // variable.variable.page is 0-indexed, convert to 1-indexed for lookup
                 let page_ref = get_page_ref(variable.variable.page + 1)?;
 
+                variable.check_signature()?;
+                let rich_text = variable.rich_text()?;
+                let features = variable.features()?;
                 let opts = DrawTextOptions {
-                    text: &variable.variable.value,
                     font_weight: Some(FontWeight::Regular),
                     font_type: Some(FontType::Cursive),
-                    font_size: variable.font_size.unwrap_or(most_used_font_size),
-                    color: variable.color,
-                    x: variable.variable.x,
-                    y: variable.variable.y,
-                    w: variable.variable.w,
-                    h: variable.variable.h,
+                    italic: false,
                     text_alignment: None,
-                    align_v: variable.align_v.clone(),
+                    ..DrawTextOptions::from_variable(
+                        variable,
+                        &rich_text,
+                        &features,
+                        most_used_font_size,
+                    )?
                 };
-                draw_text_fit(&mut document, page_ref, opts)?;
+                draw_text_fit(&mut document, &mut fonts, page_ref, opts)?;
+            }
+            PdfVariable::Markdown(variable) => {
+                // variable.variable.page is 0-indexed, convert to 1-indexed for lookup
+                let page_ref = get_page_ref(variable.variable.page + 1)?;
+
+                let blocks = parse_markdown(&variable.variable.value);
+                let features = variable.features()?;
+                let opts = DrawTextOptions::from_markdown_variable(
+                    variable,
+                    &features,
+                    most_used_font_size,
+                )?;
+                draw_markdown(&mut document, &mut fonts, page_ref, opts, &blocks)?;
             }
             PdfVariable::Image(variable) => {
                 println!("Downloading image from: {}", variable.variable.value);
@@ -374,6 +603,69 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                         y: variable.variable.y,
                         w: variable.variable.w,
                         h: variable.variable.h,
+                        rotation: variable.variable.rotation.unwrap_or(0.0),
+                    },
+                )?;
+            }
+            PdfVariable::Checkbox(variable) => {
+                if variable.checked()? {
+                    // variable.variable.page is 0-indexed, convert to 1-indexed for lookup
+                    let page_ref = get_page_ref(variable.variable.page + 1)?;
+
+                    draw_mark(
+                        &mut document,
+                        page_ref,
+                        DrawMarkOptions {
+                            style: variable.style.unwrap_or(MarkStyle::Check),
+                            color: variable.color,
+                            x: variable.variable.x,
+                            y: variable.variable.y,
+                            w: variable.variable.w,
+                            h: variable.variable.h,
+                            rotation: variable.variable.rotation.unwrap_or(0.0),
+                        },
+                    )?;
+                }
+            }
+            PdfVariable::Radio(variable) => {
+                if variable.selected()? {
+                    // variable.variable.page is 0-indexed, convert to 1-indexed for lookup
+                    let page_ref = get_page_ref(variable.variable.page + 1)?;
+
+                    draw_mark(
+                        &mut document,
+                        page_ref,
+                        DrawMarkOptions {
+                            style: variable.style.unwrap_or(MarkStyle::Circle),
+                            color: variable.color,
+                            x: variable.variable.x,
+                            y: variable.variable.y,
+                            w: variable.variable.w,
+                            h: variable.variable.h,
+                            rotation: variable.variable.rotation.unwrap_or(0.0),
+                        },
+                    )?;
+                }
+            }
+            PdfVariable::Qr(variable) => {
+                // variable.variable.page is 0-indexed, convert to 1-indexed for lookup
+                let page_ref = get_page_ref(variable.variable.page + 1)?;
+
+                draw_qr(
+                    &mut document,
+                    page_ref,
+                    DrawQrOptions {
+                        field: &variable.variable.field,
+                        data: &variable.variable.value,
+                        error_correction: variable.error_correction.unwrap_or_default(),
+                        quiet_zone: variable.quiet_zone.unwrap_or(DEFAULT_QUIET_ZONE),
+                        color: variable.color,
+                        background_color: variable.background_color,
+                        x: variable.variable.x,
+                        y: variable.variable.y,
+                        w: variable.variable.w,
+                        h: variable.variable.h,
+                        rotation: variable.variable.rotation.unwrap_or(0.0),
                     },
                 )?;
             }
@@ -396,7 +688,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             .as_secs();
 
         for page in document.get_pages() {
-            let (_, _, page_w, page_h) = get_page_media_box(&document, &page.1)?;
+            let (page_w, page_h) = get_page_size(&document, &page.1)?;
             let verification_code =
                 format!("{generated_timestamp}-{template_hash:x}-{form_schema_hash:x}");
             add_link(
@@ -415,8 +707,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             )?;
             draw_text(
                 &mut document,
+                &mut fonts,
                 &page.1,
                 DrawTextOptions {
+                    field: "verification_code",
                     text: &format!(
                         "BetterInternship E-Sign Verification Code: {verification_code}"
                     ),
@@ -424,18 +718,18 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     font_weight: Some(FontWeight::Regular),
                     font_type: Some(FontType::SansSerif),
                     color: Some(Color::from_rgb(0x80, 0x80, 0x80)),
-                    text_alignment: None,
-                    align_v: None,
                     x: page_w - 232f32,
                     y: page_h - 20f32,
                     w: 220f32,
                     h: 12f32,
+                    ..Default::default()
                 },
             )?;
         }
     }
 
-    // Compress and save
+    // Embed the subset fonts, compress and save
+    fonts.embed(&mut document)?;
     document.compress();
 
     let output_path: String = Input::new()
@@ -465,30 +759,3 @@ fn parse_color(hex: &str) -> Result<Color, Box<dyn std::error::Error>> {
     Ok(Color::from_rgb(r, g, b))
 }
 
-fn wrap_text_at_char_limit(text: &str, max_chars: usize) -> String {
-    if max_chars == 0 {
-        return text.to_string();
-    }
-
-    let mut out = String::with_capacity(text.len() + text.len() / max_chars + 1);
-    let mut count = 0usize;
-
-    for ch in text.chars() {
-        if ch == '\n' {
-            count = 0;
-            out.push(ch);
-            continue;
-        }
-
-        if count >= max_chars {
-            out.push('\n');
-            count = 0;
-        }
-
-        out.push(ch);
-        count += 1;
-    }
-
-    out
-}
-
@@ -1,15 +1,9 @@
 use std::{
     io::Cursor,
-    time::{Instant, SystemTime, UNIX_EPOCH},
+    time::{SystemTime, UNIX_EPOCH},
 };
 
-use aws_sdk_s3::primitives::ByteStream;
-use axum::{
-    Json,
-    body::{Body, Bytes},
-    extract::State,
-    response::Response,
-};
+use axum::{Json, body::Body, extract::State, response::Response};
 use crc32fast::hash;
 use image::ImageFormat;
 use serde::{Deserialize, Serialize};
@@ -17,20 +11,25 @@ use url::Url;
 
 use crate::{
     error::{AppError, GenericError},
-    fonts::{CURSIVE_REGULAR_BYTES, FontType, FontWeight},
+    fonts::{FontType, FontWeight},
     pdf::{
         PdfVariable,
         acroform::remove_acroforms,
         color::Color,
-        font::{embed_ttf_font, get_most_used_font_size, reference_base_fonts},
-        get_page_media_box,
+        font::{FontRegistry, get_most_used_font_size},
+        get_page_contents, get_page_size,
         image::{DrawImageOptions, draw_image},
         link::{DrawLinkOptions, add_link},
-        text::{DrawTextOptions, draw_text, draw_text_fit, draw_text_wrap, draw_text_wrap_fit},
+        mark::{DrawMarkOptions, MarkStyle, draw_mark},
+        markdown::parse_markdown,
+        qr::{DEFAULT_QUIET_ZONE, DrawQrOptions, draw_qr},
+        text::{
+            DrawTextOptions, OverflowTemplate, TextBox, draw_markdown, draw_text, draw_text_fit,
+            draw_text_flow, draw_text_wrap_fit,
+        },
     },
     pdf::{TextAlignment, VerticalAlign},
     state::AppState,
-    util::get_file_url,
 };
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -55,11 +54,11 @@ pub struct GeneratePdfResponse {
 
 #[axum::debug_handler]
 pub async fn generate_pdf(
-    State(state): State<AppState>,
+    State(_state): State<AppState>,
     Json(payload): Json<GeneratePdfRequest>,
 ) -> Result</*Json<GeneratePdfResponse>*/ Response<Body>, AppError> {
     let url = Url::parse(&payload.template_url)?;
-    let filename = url
+    let _filename = url
         .path_segments()
         .ok_or(GenericError("Cannot be a URL".into()))?
         .next_back()
@@ -76,18 +75,19 @@ pub async fn generate_pdf(
         .unwrap()
         .as_secs();
 
+    let verification_code = format!("{generated_timestamp}-{template_hash:x}-{form_schema_hash:x}");
+    let verification_url =
+        format!("https://docs.betterinternship.com?verification-code={verification_code}");
+
     let mut document = lopdf::Document::load_mem(&template)?;
     remove_acroforms(&mut document);
     let most_used_font_size = get_most_used_font_size(&document);
 
     // get page refs
     let page_refs = document.get_pages();
+    let page_contents = get_page_contents(&document);
 
-    reference_base_fonts(&mut document)?;
-
-    let mut has_signature_embedded = false;
-
-    let page = document.page_iter().next().unwrap();
+    let mut fonts = FontRegistry::new();
 
     for variable in &payload.variables {
         match variable {
@@ -96,59 +96,104 @@ pub async fn generate_pdf(
                     .get(&(variable.variable.page as u32))
                     .ok_or(GenericError("Page not found".into()))?;
 
-                let opts = DrawTextOptions {
-                    text: &variable.variable.value,
-                    font_weight: None,
-                    font_type: Some(FontType::SansSerif),
-                    font_size: variable.font_size.unwrap_or(most_used_font_size),
-                    color: variable.color,
-                    x: variable.variable.x,
-                    y: variable.variable.y,
-                    w: variable.variable.w,
-                    h: variable.variable.h,
-                    text_alignment: variable.align_h.clone(),
-                    align_v: variable.align_v.clone(),
-                };
+                let rich_text = variable.rich_text()?;
+                let features = variable.features()?;
+                let opts = DrawTextOptions::from_variable(
+                    variable,
+                    &rich_text,
+                    &features,
+                    most_used_font_size,
+                )?;
 
                 // if variable.wrap.unwrap_or(false) {
-                //     draw_text_wrap(&mut document, page_ref, opts)?;
+                //     draw_text_wrap(&mut document, &mut fonts, page_ref, opts)?;
                 // } else {
-                //     draw_text_fit(&mut document, page_ref, opts)?;
+                //     draw_text_fit(&mut document, &mut fonts, page_ref, opts)?;
                 // }
-                //draw_text_wrap(&mut document, page_ref, opts)?; //use this for wrapping newline below (does not dynamically shrinks)
-                //draw_text_fit(&mut document, page_ref, opts)?;
-                draw_text_wrap_fit(&mut document, page_ref, opts)?; // use this for dynamic shrinking however not stable yet 
-            }
-            PdfVariable::Signature(variable) => {
-                if !has_signature_embedded {
-                    embed_ttf_font(
+                //draw_text_wrap(&mut document, &mut fonts, page_ref, opts)?; //use this for wrapping newline below (does not dynamically shrinks)
+                //draw_text_fit(&mut document, &mut fonts, page_ref, opts)?;
+                if variable.continuations.is_some() || variable.overflow_page.is_some() {
+                    let text_box = |page: usize, x, y, w, h| -> Result<TextBox, GenericError> {
+                        Ok(TextBox {
+                            page: *page_refs
+                                .get(&(page as u32))
+                                .ok_or(GenericError("Page not found".into()))?,
+                            x,
+                            y,
+                            w,
+                            h,
+                        })
+                    };
+                    let continuations = variable
+                        .continuations
+                        .iter()
+                        .flatten()
+                        .map(|b| text_box(b.page, b.x, b.y, b.w, b.h))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    let overflow_page = variable
+                        .overflow_page
+                        .as_ref()
+                        .map(|p| text_box(p.template_page, p.x, p.y, p.w, p.h))
+                        .transpose()?
+                        .map(|text_box| OverflowTemplate {
+                            text_box,
+                            contents: page_contents.get(&text_box.page),
+                        });
+
+                    draw_text_flow(
                         &mut document,
-                        "ABCDEE+ItalianoRegular",
-                        "pdf-Cursive",
-                        CURSIVE_REGULAR_BYTES,
+                        &mut fonts,
+                        page_ref,
+                        opts,
+                        &continuations,
+                        overflow_page,
                     )?;
-                    has_signature_embedded = true;
+                } else {
+                    draw_text_wrap_fit(&mut document, &mut fonts, page_ref, opts)?; // use this for dynamic shrinking however not stable yet 
                 }
-
+            }
+            PdfVariable::Signature(variable) => {
+                variable.check_signature()?;
+                let rich_text = variable.rich_text()?;
+                let features = variable.features()?;
                 let opts = DrawTextOptions {
-                    text: &variable.variable.value,
                     font_weight: Some(FontWeight::Regular),
                     font_type: Some(FontType::Cursive),
-                    font_size: variable.font_size.unwrap_or(most_used_font_size),
-                    color: variable.color,
-                    x: variable.variable.x,
-                    y: variable.variable.y,
-                    w: variable.variable.w,
-                    h: variable.variable.h,
+                    italic: false,
                     text_alignment: variable.align_h.clone().or(Some(TextAlignment::Center)), //TODO: make this option if you want hardcoded alignment -> variable.align_h.clone().or(Some(TextAlignment::Center)) or Some(TextAlignment::Center)
                     align_v: variable.align_v.clone().or(Some(VerticalAlign::Bottom)), //TODO: make this option if you want hardcoded alignment -> variable.align_v.clone().or(Some(VerticalAlign::Bottom)) or Some(VerticalAlign::Bottom),
+                    ..DrawTextOptions::from_variable(
+                        variable,
+                        &rich_text,
+                        &features,
+                        most_used_font_size,
+                    )?
                 };
                 draw_text_fit(
                     &mut document,
+                    &mut fonts,
+                    page_refs
+                        .get(&(variable.variable.page as u32))
+                        .ok_or(GenericError("Page not found".into()))?,
+                    opts,
+                )?;
+            }
+            PdfVariable::Markdown(variable) => {
+                let blocks = parse_markdown(&variable.variable.value);
+                let features = variable.features()?;
+                let opts = DrawTextOptions::from_markdown_variable(
+                    variable,
+                    &features,
+                    most_used_font_size,
+                )?;
+                draw_markdown(
+                    &mut document,
+                    &mut fonts,
                     page_refs
                         .get(&(variable.variable.page as u32))
                         .ok_or(GenericError("Page not found".into()))?,
                     opts,
+                    &blocks,
                 )?;
             }
             PdfVariable::Image(variable) => {
@@ -175,6 +220,70 @@ pub async fn generate_pdf(
                         y: variable.variable.y,
                         w: variable.variable.w,
                         h: variable.variable.h,
+                        rotation: variable.variable.rotation.unwrap_or(0.0),
+                    },
+                )?;
+            }
+            PdfVariable::Checkbox(variable) => {
+                if variable.checked()? {
+                    draw_mark(
+                        &mut document,
+                        page_refs
+                            .get(&(variable.variable.page as u32))
+                            .ok_or(GenericError("Page not found".into()))?,
+                        DrawMarkOptions {
+                            style: variable.style.unwrap_or(MarkStyle::Check),
+                            color: variable.color,
+                            x: variable.variable.x,
+                            y: variable.variable.y,
+                            w: variable.variable.w,
+                            h: variable.variable.h,
+                            rotation: variable.variable.rotation.unwrap_or(0.0),
+                        },
+                    )?;
+                }
+            }
+            PdfVariable::Radio(variable) => {
+                if variable.selected()? {
+                    draw_mark(
+                        &mut document,
+                        page_refs
+                            .get(&(variable.variable.page as u32))
+                            .ok_or(GenericError("Page not found".into()))?,
+                        DrawMarkOptions {
+                            style: variable.style.unwrap_or(MarkStyle::Circle),
+                            color: variable.color,
+                            x: variable.variable.x,
+                            y: variable.variable.y,
+                            w: variable.variable.w,
+                            h: variable.variable.h,
+                            rotation: variable.variable.rotation.unwrap_or(0.0),
+                        },
+                    )?;
+                }
+            }
+            PdfVariable::Qr(variable) => {
+                draw_qr(
+                    &mut document,
+                    page_refs
+                        .get(&(variable.variable.page as u32))
+                        .ok_or(GenericError("Page not found".into()))?,
+                    DrawQrOptions {
+                        field: &variable.variable.field,
+                        data: if variable.verification_url.unwrap_or(false) {
+                            &verification_url
+                        } else {
+                            &variable.variable.value
+                        },
+                        error_correction: variable.error_correction.unwrap_or_default(),
+                        quiet_zone: variable.quiet_zone.unwrap_or(DEFAULT_QUIET_ZONE),
+                        color: variable.color,
+                        background_color: variable.background_color,
+                        x: variable.variable.x,
+                        y: variable.variable.y,
+                        w: variable.variable.w,
+                        h: variable.variable.h,
+                        rotation: variable.variable.rotation.unwrap_or(0.0),
                     },
                 )?;
             }
@@ -183,17 +292,12 @@ pub async fn generate_pdf(
 
     for page in document.get_pages() {
         if payload.include_hash_in_header.unwrap_or(false) {
-            let (_, _, page_w, page_h) = get_page_media_box(&document, &page.1)?;
-            let verification_code =
-                format!("{generated_timestamp}-{template_hash:x}-{form_schema_hash:x}");
+            let (page_w, page_h) = get_page_size(&document, &page.1)?;
             add_link(
                 &mut document,
                 &page.1,
                 DrawLinkOptions {
-                    link: &format!(
-                        "https://docs.betterinternship.com?verification-code={}",
-                        verification_code
-                    ),
+                    link: &verification_url,
                     x: page_w - 350f32, //old 232f32
                     y: page_h - 20f32,  //old 20f32
                     w: 220f32,          //old 220f32
@@ -202,8 +306,10 @@ pub async fn generate_pdf(
             )?;
             draw_text(
                 &mut document,
+                &mut fonts,
                 &page.1,
                 DrawTextOptions {
+                    field: "verification_code",
                     text: &format!(
                         "BetterInternship E-Sign Verification Code: {verification_code}"
                     ),
@@ -211,17 +317,17 @@ pub async fn generate_pdf(
                     font_weight: Some(FontWeight::Regular),
                     font_type: Some(FontType::SansSerif),
                     color: Some(Color::from_rgb(0x80, 0x80, 0x80)),
-                    text_alignment: None,
-                    align_v: None,
                     x: page_w - 350f32, //old 232f32
                     y: page_h - 20f32,  //old 20f32
                     w: 220f32,          //old 220f32
                     h: 12f32,           //old 9f32
+                    ..Default::default()
                 },
             )?;
         }
     }
 
+    fonts.embed(&mut document)?;
     document.compress();
 
     let mut out_document = Vec::new();
@@ -11,6 +11,35 @@ pub struct Env {
     pub s3_config: aws_sdk_s3::Config,
     pub s3_bucket: String,
     pub s3_public_url_format: String,
+    // Paths of TrueType fonts tried, in order, for characters the selected font does not cover.
+    // Fonts need `glyf` outlines: CFF based fonts (most OpenType CJK fonts) and color emoji
+    // fonts (CBDT, COLR, sbix) cannot be embedded and are skipped with a warning.
+    pub fallback_fonts: Vec<String>,
+}
+
+// Noto faces of the Indic scripts, which the bundled fonts have no glyphs for, where Debian's
+// `fonts-noto-core` installs them (see the Dockerfile)
+const DEFAULT_FALLBACK_FONTS: &str = "\
+    /usr/share/fonts/truetype/noto/NotoSansDevanagari-Regular.ttf,\
+    /usr/share/fonts/truetype/noto/NotoSansBengali-Regular.ttf,\
+    /usr/share/fonts/truetype/noto/NotoSansGurmukhi-Regular.ttf,\
+    /usr/share/fonts/truetype/noto/NotoSansGujarati-Regular.ttf,\
+    /usr/share/fonts/truetype/noto/NotoSansOriya-Regular.ttf,\
+    /usr/share/fonts/truetype/noto/NotoSansTamil-Regular.ttf,\
+    /usr/share/fonts/truetype/noto/NotoSansTelugu-Regular.ttf,\
+    /usr/share/fonts/truetype/noto/NotoSansKannada-Regular.ttf,\
+    /usr/share/fonts/truetype/noto/NotoSansMalayalam-Regular.ttf";
+
+// Comma separated paths from `FALLBACK_FONTS`, see `Env::fallback_fonts`. The Indic faces of
+// `DEFAULT_FALLBACK_FONTS` if unset, an empty value turns the fallback fonts off.
+pub fn get_fallback_font_paths() -> Vec<String> {
+    var("FALLBACK_FONTS")
+        .unwrap_or(DEFAULT_FALLBACK_FONTS.into())
+        .split(',')
+        .map(str::trim)
+        .filter(|path| !path.is_empty())
+        .map(String::from)
+        .collect()
 }
 
 pub fn get_env() -> Env {
@@ -47,5 +76,6 @@ pub fn get_env() -> Env {
         s3_config,
         s3_bucket: var("S3_BUCKET").expect("Expected S3_BUCKET!"),
         s3_public_url_format: var("S3_PUBLIC_URL_FORMAT").expect("Expected S3_PUBLIC_URL_FORMAT!"),
+        fallback_fonts: get_fallback_font_paths(),
     }
 }
@@ -1,9 +1,11 @@
 use std::{error::Error, fmt::Display};
 
 use axum::{
+    Json,
     http::StatusCode,
     response::{IntoResponse, Response},
 };
+use serde::Serialize;
 
 pub type BoxedError = anyhow::Error;
 
@@ -19,10 +21,112 @@ impl Display for GenericError {
 
 impl Error for GenericError {}
 
+// Text larger than its box for a variable whose overflow policy is to fail. Sizes are in points.
+#[derive(Debug, Serialize)]
+#[serde(tag = "error", rename = "text_overflow")]
+pub struct TextOverflowError {
+    pub field: String,
+    pub font_size: f32,
+    pub text_width: f32,
+    pub text_height: f32,
+    pub box_width: f32,
+    pub box_height: f32,
+}
+
+impl Display for TextOverflowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Text of field {} ({}x{}) does not fit its box ({}x{}) at font size {}",
+            self.field,
+            self.text_width,
+            self.text_height,
+            self.box_width,
+            self.box_height,
+            self.font_size
+        )
+    }
+}
+
+impl Error for TextOverflowError {}
+
+// Text that would need more overflow pages than a single request may append
+#[derive(Debug, Serialize)]
+#[serde(tag = "error", rename = "overflow_page_limit")]
+pub struct OverflowPageLimitError {
+    pub field: String,
+    pub max_pages: usize,
+}
+
+impl Display for OverflowPageLimitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Text of field {} needs more than {} overflow pages",
+            self.field, self.max_pages
+        )
+    }
+}
+
+impl Error for OverflowPageLimitError {}
+
+// Value longer than the comb of its variable, in graphemes
+#[derive(Debug, Serialize)]
+#[serde(tag = "error", rename = "comb_overflow")]
+pub struct CombOverflowError {
+    pub field: String,
+    pub characters: usize,
+    pub cells: usize,
+}
+
+impl Display for CombOverflowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Value of field {} has {} characters, more than the {} cells of its comb",
+            self.field, self.characters, self.cells
+        )
+    }
+}
+
+impl Error for CombOverflowError {}
+
+// Options of a variable that cannot be drawn together, or an option out of its range
+#[derive(Debug, Serialize)]
+#[serde(tag = "error", rename = "invalid_option")]
+pub struct InvalidOptionError {
+    pub field: String,
+    pub message: String,
+}
+
+impl Display for InvalidOptionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Field {}: {}", self.field, self.message)
+    }
+}
+
+impl Error for InvalidOptionError {}
+
+// Response for errors in the request itself, which are reported to the caller as they are
+fn client_error<E: Error + Serialize + Send + Sync + 'static>(
+    err: &anyhow::Error,
+) -> Option<Response> {
+    err.downcast_ref::<E>()
+        .map(|err| (StatusCode::UNPROCESSABLE_ENTITY, Json(err)).into_response())
+}
+
 pub struct AppError(anyhow::Error);
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
+        if let Some(response) = client_error::<TextOverflowError>(&self.0)
+            .or_else(|| client_error::<OverflowPageLimitError>(&self.0))
+            .or_else(|| client_error::<CombOverflowError>(&self.0))
+            .or_else(|| client_error::<InvalidOptionError>(&self.0))
+        {
+            return response;
+        }
+
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             format!(
@@ -1,5 +1,11 @@
+use std::{ops::Range, sync::OnceLock};
+
 use lazy_static::lazy_static;
-use rustybuzz::{Face, GlyphBuffer, UnicodeBuffer};
+use rustybuzz::{Direction, Face, Feature, GlyphBuffer, UnicodeBuffer};
+use serde::{Deserialize, Serialize};
+use unicode_bidi::{BidiClass, BidiInfo, Level, bidi_class};
+
+use crate::error::GenericError;
 
 pub static SANS_SERIF_REGULAR_BYTES: &[u8] =
     include_bytes!("../static/fonts/sans-serif/OpenSans-Regular.ttf");
@@ -49,7 +55,16 @@ pub static MONO_LIGHT_ITALIC_BYTES: &[u8] =
 pub static CURSIVE_REGULAR_BYTES: &[u8] =
     include_bytes!("../static/fonts/cursive/Italianno-Regular.ttf");
 
-#[derive(Debug, Clone, Copy)]
+// Fallback faces for scripts the family faces do not cover (Arabic, Hebrew, ...), Indic scripts
+// use the system Noto faces, see `INDIC_SCRIPTS`
+pub static FALLBACK_REGULAR_BYTES: &[u8] =
+    include_bytes!("../static/fonts/fallback/DejaVuSans.ttf");
+
+pub static FALLBACK_BOLD_BYTES: &[u8] =
+    include_bytes!("../static/fonts/fallback/DejaVuSans-Bold.ttf");
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum FontType {
     Serif,
     SansSerif,
@@ -57,7 +72,8 @@ pub enum FontType {
     Cursive,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum FontWeight {
     Light,
     Regular,
@@ -111,262 +127,405 @@ lazy_static! {
 
     pub static ref CURSIVE_REGULAR: Face<'static> =
         Face::from_slice(CURSIVE_REGULAR_BYTES, 0).expect("Invalid CURSIVE_REGULAR font");
+
+    // FALLBACK
+    pub static ref FALLBACK_REGULAR: Face<'static> =
+        Face::from_slice(FALLBACK_REGULAR_BYTES, 0).expect("Invalid FALLBACK_REGULAR font");
+    pub static ref FALLBACK_BOLD: Face<'static> =
+        Face::from_slice(FALLBACK_BOLD_BYTES, 0).expect("Invalid FALLBACK_BOLD font");
 }
 
-pub fn get_font(
-    font_type: &FontType,
-    font_weight: &FontWeight,
-    italics: bool,
-) -> &'static Face<'static> {
-    match font_type {
-        FontType::SansSerif => match font_weight {
-            FontWeight::Regular => match italics {
-                true => &SANS_SERIF_REGULAR_ITALIC,
-                false => &SANS_SERIF_REGULAR,
-            },
-            FontWeight::Light => match italics {
-                true => &SANS_SERIF_LIGHT_ITALIC,
-                false => &SANS_SERIF_LIGHT,
-            },
-            FontWeight::Bold => match italics {
-                true => &SANS_SERIF_BOLD_ITALIC,
-                false => &SANS_SERIF_BOLD,
-            },
-        },
-        FontType::Serif => match font_weight {
-            FontWeight::Regular | FontWeight::Light => match italics {
-                true => &SERIF_REGULAR_ITALIC,
-                false => &SERIF_REGULAR,
-            },
-            FontWeight::Bold => match italics {
-                true => &SERIF_BOLD_ITALIC,
-                false => &SERIF_BOLD,
-            },
-        },
-        FontType::Mono => match font_weight {
-            FontWeight::Regular => match italics {
-                true => &MONO_REGULAR_ITALIC,
-                false => &MONO_REGULAR,
-            },
-            FontWeight::Light => match italics {
-                true => &MONO_LIGHT_ITALIC,
-                false => &MONO_LIGHT,
-            },
-            FontWeight::Bold => match italics {
-                true => &MONO_BOLD,
-                false => &MONO_BOLD_ITALIC,
-            },
-        },
-        FontType::Cursive => &CURSIVE_REGULAR,
+// A face together with the resource name it is embedded under. Text is always measured and
+// painted with the same `PdfFont`.
+#[derive(Clone, Copy)]
+pub struct PdfFont {
+    pub face: &'static Face<'static>,
+    pub name: &'static str,
+}
+
+impl PdfFont {
+    fn new(face: &'static Face<'static>, name: &'static str) -> Self {
+        Self { face, name }
     }
 }
 
-pub fn get_font_pdf_name(
-    font_type: &FontType,
-    font_weight: &FontWeight,
-    italics: bool,
-) -> &'static str {
+pub fn get_font(font_type: &FontType, font_weight: &FontWeight, italics: bool) -> PdfFont {
     match font_type {
         FontType::SansSerif => match font_weight {
             FontWeight::Regular => match italics {
-                true => "pdf-SansSerif",
-                false => "pdf-SansSerif",
+                true => PdfFont::new(&SANS_SERIF_REGULAR_ITALIC, "pdf-SansSerif-Italic"),
+                false => PdfFont::new(&SANS_SERIF_REGULAR, "pdf-SansSerif"),
             },
             FontWeight::Light => match italics {
-                true => "pdf-SansSerif",
-                false => "pdf-SansSerif",
+                true => PdfFont::new(&SANS_SERIF_LIGHT_ITALIC, "pdf-SansSerif-LightItalic"),
+                false => PdfFont::new(&SANS_SERIF_LIGHT, "pdf-SansSerif-Light"),
             },
             FontWeight::Bold => match italics {
-                true => "pdf-SansSerif",
-                false => "pdf-SansSerif",
+                true => PdfFont::new(&SANS_SERIF_BOLD_ITALIC, "pdf-SansSerif-BoldItalic"),
+                false => PdfFont::new(&SANS_SERIF_BOLD, "pdf-SansSerif-Bold"),
             },
         },
+        // There is no light serif face, light falls back to regular
         FontType::Serif => match font_weight {
-            FontWeight::Regular => match italics {
-                true => "pdf-Serif",
-                false => "pdf-Serif",
-            },
-            FontWeight::Light => match italics {
-                true => "pdf-Serif",
-                false => "pdf-Serif",
+            FontWeight::Regular | FontWeight::Light => match italics {
+                true => PdfFont::new(&SERIF_REGULAR_ITALIC, "pdf-Serif-Italic"),
+                false => PdfFont::new(&SERIF_REGULAR, "pdf-Serif"),
             },
             FontWeight::Bold => match italics {
-                true => "pdf-Serif",
-                false => "pdf-Serif",
+                true => PdfFont::new(&SERIF_BOLD_ITALIC, "pdf-Serif-BoldItalic"),
+                false => PdfFont::new(&SERIF_BOLD, "pdf-Serif-Bold"),
             },
         },
         FontType::Mono => match font_weight {
             FontWeight::Regular => match italics {
-                true => "pdf-Mono",
-                false => "pdf-Mono",
+                true => PdfFont::new(&MONO_REGULAR_ITALIC, "pdf-Mono-Italic"),
+                false => PdfFont::new(&MONO_REGULAR, "pdf-Mono"),
             },
             FontWeight::Light => match italics {
-                true => "pdf-Mono",
-                false => "pdf-Mono",
+                true => PdfFont::new(&MONO_LIGHT_ITALIC, "pdf-Mono-LightItalic"),
+                false => PdfFont::new(&MONO_LIGHT, "pdf-Mono-Light"),
             },
             FontWeight::Bold => match italics {
-                true => "pdf-Mono",
-                false => "pdf-Mono",
+                true => PdfFont::new(&MONO_BOLD_ITALIC, "pdf-Mono-BoldItalic"),
+                false => PdfFont::new(&MONO_BOLD, "pdf-Mono-Bold"),
             },
         },
-        FontType::Cursive => "pdf-Cursive",
+        FontType::Cursive => PdfFont::new(&CURSIVE_REGULAR, "pdf-Cursive"),
     }
 }
 
-// Estimate text width in points using ttf-parser
-pub fn text_width(face: &Face, text: &str, font_size: f32) -> f32 {
-    let upem = face.units_per_em() as f32;
-    let scale = font_size / upem;
+// Read a face that lives for the whole process, like the bundled ones. Only TrueType outlines
+// can be embedded as FontFile2, so CFF based fonts (most OpenType CJK fonts) and color fonts
+// (CBDT, COLR, sbix emoji) are rejected.
+fn load_face(path: &str) -> Result<&'static Face<'static>, String> {
+    let data = std::fs::read(path).map_err(|err| format!("cannot be read: {err}"))?;
+    let data: &'static [u8] = Box::leak(data.into_boxed_slice());
+    let face = Face::from_slice(data, 0).ok_or("is not a valid font")?;
+    if face.tables().glyf.is_none() {
+        return Err("has no TrueType outlines".into());
+    }
+
+    Ok(Box::leak(Box::new(face)))
+}
 
-    // Shape the text (includes kerning, ligatures, etc.)
-    let mut buffer = UnicodeBuffer::new();
-    buffer.push_str(text);
-    let glyph_buffer: GlyphBuffer = rustybuzz::shape(face, &[], buffer);
+static FALLBACK_CHAIN: OnceLock<Vec<PdfFont>> = OnceLock::new();
 
-    // Sum advances in font units, then scale to pixels
-    glyph_buffer
-        .glyph_positions()
+// Load the extra fallback faces from the paths of `Env::fallback_fonts`. They are tried in order
+// before the bundled fallback faces, so deployments can add coverage for CJK, emoji, math, etc.
+// Faces that cannot be used are logged and left out. Only the first call has an effect.
+pub fn load_fallback_fonts(paths: &[String]) {
+    let chain = paths
         .iter()
-        .map(|pos| pos.x_advance as f32 * scale)
-        .sum()
+        .filter_map(|path| match load_face(path) {
+            Ok(face) => Some(face),
+            Err(err) => {
+                log::warn!("Fallback font {path} {err}, skipping");
+                None
+            }
+        })
+        .enumerate()
+        .map(|(index, face)| {
+            let name: &'static str = Box::leak(format!("pdf-Fallback-{index}").into_boxed_str());
+            PdfFont::new(face, name)
+        })
+        .collect();
+
+    let _ = FALLBACK_CHAIN.set(chain);
 }
 
-// Wrap text by inserting newlines
-pub fn wrap_text(face: &Face, text: &str, font_size: f32, max_width: f32) -> (String, usize, f32) {
-    let mut wrapped = String::new();
-    let mut current = String::new();
-    let mut line_count = 0usize;
-    let mut high_width = 0f32;
-
-    for word in text.split_whitespace() {
-        let text = if current.is_empty() {
-            word.to_string()
-        } else {
-            format!("{current} {word}")
-        };
+// Faces tried, in order, for characters the selected face has no glyph for: the configured
+// fallback faces, then the bundled DejaVu Sans (Arabic, Hebrew, ...)
+pub fn get_fallback_fonts(font: PdfFont) -> impl Iterator<Item = PdfFont> {
+    let bundled = if font.face.is_bold() {
+        PdfFont::new(&FALLBACK_BOLD, "pdf-Fallback-Bold")
+    } else {
+        PdfFont::new(&FALLBACK_REGULAR, "pdf-Fallback")
+    };
+
+    FALLBACK_CHAIN
+        .get()
+        .into_iter()
+        .flatten()
+        .copied()
+        .chain(std::iter::once(bundled))
+}
+
+// Extra space, in points, added after every glyph and after every space
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Spacing {
+    pub letter: f32,
+    pub word: f32,
+}
 
-        let width = text_width(face, &text, font_size);
+// A piece of text shaped with a single face in a single direction
+pub struct ShapedRun {
+    pub font: PdfFont,
+    // Where the piece starts in the shaped text
+    pub offset: usize,
+    pub text: String,
+    pub glyph_buffer: GlyphBuffer,
+}
 
-        if width <= max_width {
-            current = text;
-        } else {
-            line_count += 1;
-            high_width = if width > high_width {
+impl ShapedRun {
+    pub fn width(&self, font_size: f32, spacing: Spacing) -> f32 {
+        let scale = font_size / self.font.face.units_per_em() as f32;
+        self.glyph_buffer
+            .glyph_infos()
+            .iter()
+            .zip(self.glyph_buffer.glyph_positions())
+            .map(|(info, pos)| {
+                let mut width = pos.x_advance as f32 * scale;
+                // Marks do not advance and are not spaced from their base
+                if pos.x_advance != 0 {
+                    width += spacing.letter;
+                }
+                if self.text[info.cluster as usize..].starts_with(' ') {
+                    width += spacing.word;
+                }
                 width
-            } else {
-                high_width
-            };
+            })
+            .sum()
+    }
+}
+
+fn covers(font: PdfFont, ch: char) -> bool {
+    font.face.glyph_index(ch).is_some()
+}
 
-            if !wrapped.is_empty() {
-                wrapped.push('\n');
+// Face of the segment `offset` falls in. Segments are given by their start, in order.
+fn segment_font(segments: &[(usize, PdfFont)], offset: usize) -> PdfFont {
+    segments
+        .iter()
+        .rev()
+        .find(|(start, _)| *start <= offset)
+        .or(segments.first())
+        .map(|(_, font)| *font)
+        .expect("Text shaped without a font")
+}
+
+// Split the text from `base` on into pieces that can each be drawn with one face, preferring
+// the face of its segment and using the first face of the fallback chain that covers a
+// character otherwise. Pieces never cross segment starts. Spaces and combining marks stay with
+// the preceding piece so words and clusters are not broken apart. Returns the pieces with their
+// offsets in the whole text.
+fn split_by_coverage<'a>(
+    segments: &[(usize, PdfFont)],
+    text: &'a str,
+    base: usize,
+) -> Vec<(PdfFont, usize, &'a str)> {
+    let mut pieces: Vec<(PdfFont, usize, &str)> = vec![];
+    let mut current: Option<(PdfFont, usize)> = None;
+
+    for (index, ch) in text.char_indices() {
+        let font = segment_font(segments, base + index);
+        let segment_start = segments.iter().any(|(start, _)| *start == base + index);
+
+        // Marks, joiners and variation selectors belong to the cluster before them even when
+        // the face has no glyph of their own, shaping drops them in that case
+        let char_font = match (current, bidi_class(ch)) {
+            _ if segment_start && covers(font, ch) => font,
+            (Some((current_font, _)), BidiClass::NSM | BidiClass::BN) => current_font,
+            (Some((current_font, _)), BidiClass::WS) if covers(current_font, ch) => current_font,
+            _ if covers(font, ch) => font,
+            _ => get_fallback_fonts(font)
+                .find(|fallback| covers(*fallback, ch))
+                .unwrap_or(font),
+        };
+
+        match current {
+            Some((current_font, start)) if segment_start || current_font.name != char_font.name => {
+                pieces.push((current_font, base + start, &text[start..index]));
+                current = Some((char_font, index));
             }
-            wrapped += &current;
-            current = word.to_string();
+            None => current = Some((char_font, index)),
+            _ => {}
         }
     }
-    if !current.is_empty() {
-        line_count += 1;
-        let width = text_width(face, text, font_size);
-        high_width = if width > high_width {
-            width
-        } else {
-            high_width
-        };
-        if !wrapped.is_empty() {
-            wrapped.push('\n');
-        }
-        wrapped += &current;
+
+    if let Some((current_font, start)) = current {
+        pieces.push((current_font, base + start, &text[start..]));
+    }
+
+    pieces
+}
+
+// Direction of each paragraph of the text, from its first strong character, by the range of
+// the paragraph. True for right to left.
+pub fn paragraph_directions(text: &str) -> Vec<(Range<usize>, bool)> {
+    BidiInfo::new(text, None)
+        .paragraphs
+        .iter()
+        .map(|paragraph| (paragraph.range.clone(), paragraph.level.is_rtl()))
+        .collect()
+}
+
+// Whether the paragraph direction of the text (from its first strong character) is right
+// to left
+pub fn is_rtl(text: &str) -> bool {
+    paragraph_directions(text)
+        .first()
+        .is_some_and(|(_, rtl)| *rtl)
+}
+
+// OpenType feature from its setting: a tag such as `smcp`, `tnum` or `ss01` to turn it on, the
+// tag prefixed with `-` to turn it off, or `tag=value` to pick an alternate. Features apply to
+// the whole text.
+pub fn parse_feature(setting: &str) -> Result<Feature, GenericError> {
+    let feature: Feature = setting
+        .trim()
+        .parse()
+        .map_err(|err| GenericError(format!("Invalid font feature {setting}: {err}")))?;
+    if feature.start != 0 || feature.end != u32::MAX {
+        return Err(GenericError(format!(
+            "Font feature {setting} is limited to a range, features apply to the whole text"
+        )));
     }
-    (wrapped, line_count, high_width)
+
+    Ok(feature)
 }
 
-/* 
-pub fn fit_text_font_size(
-    face: &Face,
+// Shape a single line of text. The line is reordered following the Unicode bidirectional
+// algorithm, and every run is shaped with its own direction and script, so the returned runs
+// are in visual (left to right) order.
+pub fn shape_text(font: PdfFont, text: &str, features: &[Feature]) -> Vec<ShapedRun> {
+    shape_text_segments(text, &[(0, font)], features, None)
+}
+
+// Shape a single line of text made of segments set in different faces, given by where they
+// start in the line, with the OpenType features on top of the defaults of the faces. Runs never
+// cross segment starts. The line is reordered in a paragraph running right to left if `rtl`,
+// or in the direction of its own first strong character if unset. Wrapped lines take the
+// direction of their paragraph, they can start with a word of the other direction.
+pub fn shape_text_segments(
     text: &str,
-    width: f32,
-    height: f32,
-) -> Option<(String, f32)> {
-    let mut size = height;
-    let mut wrapped_text = String::new();
-    let mut line_count;
-    let mut calc_width = width + 1f32;
-    let mut calc_height = height + 1f32;
-
-    while size > 1f32 && (calc_width > width || calc_height > height) {
-        // wrap_text likely needs height as usize
-        (wrapped_text, line_count, calc_width) = wrap_text(face, text, size, width);
-        println!("LINES::{}", text.clone().split("\n").count());
-        calc_height = size * line_count as f32;
-        println!("{calc_width} {width} {calc_height} {height} {size}");
-
-        if calc_width > width || calc_height > height {
-            size -= 1f32;
+    segments: &[(usize, PdfFont)],
+    features: &[Feature],
+    rtl: Option<bool>,
+) -> Vec<ShapedRun> {
+    let level = rtl.map(|rtl| if rtl { Level::rtl() } else { Level::ltr() });
+    let bidi_info = BidiInfo::new(text, level);
+    let mut runs = vec![];
+
+    for paragraph in &bidi_info.paragraphs {
+        let (levels, visual_runs) = bidi_info.visual_runs(paragraph, paragraph.range.clone());
+
+        for range in visual_runs {
+            let rtl = levels[range.start].is_rtl();
+
+            let base = range.start;
+            let mut pieces = split_by_coverage(segments, &text[range], base);
+            if rtl {
+                pieces.reverse();
+            }
+
+            for (piece_font, offset, piece) in pieces {
+                let mut buffer = UnicodeBuffer::new();
+                buffer.push_str(piece);
+                buffer.set_direction(if rtl {
+                    Direction::RightToLeft
+                } else {
+                    Direction::LeftToRight
+                });
+                buffer.guess_segment_properties();
+
+                runs.push(ShapedRun {
+                    font: piece_font,
+                    offset,
+                    text: piece.to_string(),
+                    glyph_buffer: rustybuzz::shape(piece_font.face, features, buffer),
+                });
+            }
         }
     }
 
-    println!("CCCC::{calc_height}");
+    runs
+}
+
+// Estimate text width in points using the shaped runs of the text
+pub fn text_width(
+    font: PdfFont,
+    text: &str,
+    font_size: f32,
+    spacing: Spacing,
+    features: &[Feature],
+) -> f32 {
+    // Shaping includes kerning, ligatures, etc.
+    shape_text(font, text, features)
+        .iter()
+        .map(|run| run.width(font_size, spacing))
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sans() -> PdfFont {
+        get_font(&FontType::SansSerif, &FontWeight::Regular, false)
+    }
+
+    fn run_texts(runs: &[ShapedRun]) -> Vec<&str> {
+        runs.iter().map(|run| run.text.as_str()).collect()
+    }
 
-    if (calc_width > width || calc_height > height) || size < 1f32 {
-        None
-    } else {
-        Some((wrapped_text, size))
+    #[test]
+    fn lines_are_reordered_in_the_direction_of_their_paragraph() {
+        let line = "abc \u{5E2}\u{5D5}\u{5DC}\u{5DD}";
+
+        // On its own the line runs left to right from its first letter
+        let runs = shape_text_segments(line, &[(0, sans())], &[], None);
+        assert_eq!(run_texts(&runs)[0], "abc ");
+
+        // Wrapped from a right to left paragraph the Latin word ends up on the right
+        let runs = shape_text_segments(line, &[(0, sans())], &[], Some(true));
+        assert_eq!(run_texts(&runs).last(), Some(&"abc"));
+        assert_eq!(runs[0].glyph_buffer.len(), 5);
     }
-}*/
 
-pub fn fit_text_font_size(
-    face: &Face,
-    text: &str,
-    width: f32,  // box_width
-    height: f32, // box_height
-) -> Option<(String, f32)> {
-    // --- 1. Define Your Search Range ---
-    let mut min_font = 1.0;
-    let mut max_font = height; // Max guess is the box height
-
-    let mut best_font_size = 0.0;
-    let mut best_wrapped_text = String::new();
-
-    // This multiplier must match the one used in `draw_text_fit` / `draw_text_wrap`
-    // In `text.rs` you are using 1.2.
-    let line_height_multiplier = 1.2;
-
-    // --- 2. Start the Binary Search Loop ---
-    // We loop as long as the range is larger than a small precision (e.g., 0.1pt)
-    while (max_font - min_font) > 0.1 {
-        // Calculate the middle font size to test
-        let test_font = (min_font + max_font) / 2.0;
-
-        // --- 3. Run "Numerical Verification" (your `get_text_dimensions`) ---
-        // This is our "magic function" that measures the text.
-        let (wrapped_text, line_count, calc_width) =
-            wrap_text(face, text, test_font, width);
-
-        // We also need to calculate the total height
-        let calc_height = (line_count as f32) * (test_font * line_height_multiplier);
-
-        // Check if it fits
-        if calc_width <= width && calc_height <= height {
-            // IT FITS!
-            // This is a valid size. Store it as our new "best".
-            best_font_size = test_font;
-            best_wrapped_text = wrapped_text;
-
-            // Now, let's try to find an EVEN BIGGER size.
-            // We throw away all sizes smaller than this one.
-            min_font = test_font; // For floats, we set min to the last valid test
-        } else {
-            // IT'S TOO BIG!
-            // We throw away this size and all sizes larger than it.
-            max_font = test_font; // For floats, we set max to the last invalid test
-        }
+    #[test]
+    fn paragraphs_take_the_direction_of_their_first_strong_character() {
+        let text = "\u{5E9}\u{5DC}\u{5D5}\u{5DD} abc\n123 abc \u{5E9}";
+
+        let directions = paragraph_directions(text);
+
+        assert_eq!(directions.len(), 2);
+        assert!(directions[0].1);
+        assert!(!directions[1].1);
+        assert!(is_rtl(text));
     }
 
-    // --- 4. Return the Result ---
-    if best_font_size > 0.0 {
-        Some((best_wrapped_text, best_font_size))
-    } else {
-        // We never found a size that fits (e.g., box was too small)
-        None
+    #[test]
+    fn characters_missing_from_the_face_use_the_fallback_chain() {
+        // Open Sans has no Arabic
+        let text = "abc \u{633}\u{644}\u{627}\u{645}";
+
+        let runs = shape_text(sans(), text, &[]);
+
+        let fonts: Vec<&str> = runs.iter().map(|run| run.font.name).collect();
+        assert_eq!(fonts, ["pdf-SansSerif", "pdf-Fallback"]);
+        assert_eq!(run_texts(&runs), ["abc ", "\u{633}\u{644}\u{627}\u{645}"]);
+        assert!(runs.iter().all(|run| {
+            run.glyph_buffer
+                .glyph_infos()
+                .iter()
+                .all(|info| info.glyph_id != 0)
+        }));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn parse_feature_turns_features_on_and_off() {
+        let feature = parse_feature(" smcp ").unwrap();
+        assert_eq!(feature.tag, rustybuzz::ttf_parser::Tag::from_bytes(b"smcp"));
+        assert_eq!(feature.value, 1);
+
+        assert_eq!(parse_feature("-liga").unwrap().value, 0);
+        assert_eq!(parse_feature("salt=2").unwrap().value, 2);
+    }
+
+    #[test]
+    fn parse_feature_rejects_ranges_and_invalid_settings() {
+        assert!(parse_feature("smcp[3:5]").is_err());
+        assert!(parse_feature("").is_err());
+        assert!(parse_feature("not a feature").is_err());
+    }
+}
@@ -2,6 +2,7 @@ use std::sync::Arc;
 
 use axum::{Router, routing::get};
 use pdfsnap_server::env::get_env;
+use pdfsnap_server::fonts::load_fallback_fonts;
 use pdfsnap_server::pdf::pool::PdfPool;
 use tokio::net::TcpListener;
 
@@ -13,6 +14,8 @@ async fn main() {
 
     env_logger::init();
 
+    load_fallback_fonts(&env.fallback_fonts);
+
     let bind_string = format!("{}:{}", &env.host, &env.port);
 
     let pdf_pool = Arc::new(PdfPool::new(4));
@@ -1,6 +1,8 @@
+use std::str::FromStr;
+
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Color(u8, u8, u8);
 
 impl Color {
@@ -31,25 +33,31 @@ impl Serialize for Color {
     }
 }
 
-impl<'de> Deserialize<'de> for Color {
-    fn deserialize<D>(deserializer: D) -> Result<Color, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        let s = String::deserialize(deserializer)?;
+impl FromStr for Color {
+    type Err = String;
+
+    // Hex color, with or without a leading `#`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
         let s = s.trim_start_matches('#');
 
-        if s.len() != 6 {
-            return Err(serde::de::Error::custom("hex color must be 6 digits"));
+        if s.len() != 6 || !s.is_ascii() {
+            return Err("hex color must be 6 digits".into());
         }
 
-        let r = u8::from_str_radix(&s[0..2], 16)
-            .map_err(|_| serde::de::Error::custom("invalid red component"))?;
-        let g = u8::from_str_radix(&s[2..4], 16)
-            .map_err(|_| serde::de::Error::custom("invalid green component"))?;
-        let b = u8::from_str_radix(&s[4..6], 16)
-            .map_err(|_| serde::de::Error::custom("invalid blue component"))?;
+        let r = u8::from_str_radix(&s[0..2], 16).map_err(|_| "invalid red component")?;
+        let g = u8::from_str_radix(&s[2..4], 16).map_err(|_| "invalid green component")?;
+        let b = u8::from_str_radix(&s[4..6], 16).map_err(|_| "invalid blue component")?;
 
         Ok(Color(r, g, b))
     }
 }
+
+impl<'de> Deserialize<'de> for Color {
+    fn deserialize<D>(deserializer: D) -> Result<Color, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
@@ -3,27 +3,111 @@ use std::collections::BTreeMap;
 use image::EncodableLayout;
 use lopdf::{Dictionary, Document, Object, Stream, content::Content, dictionary};
 
-use crate::{error::BoxedError, fonts::CURSIVE_REGULAR};
+use rustybuzz::Face;
 
-fn create_font(document: &mut Document, font_name: &str) -> (u32, u16) {
-    let font_id = document.new_object_id();
-    let font_dict = dictionary! {
-        "Type" => "Font",
-        "Subtype" => "Type1",
-        "BaseFont" => font_name.to_string()
-    };
-    document
-        .objects
-        .insert(font_id, Object::Dictionary(font_dict));
-
-    font_id
+use crate::error::BoxedError;
+
+struct FontUsage {
+    face: &'static Face<'static>,
+    // glyph ID -> text the glyph was shaped from
+    glyphs: BTreeMap<u16, String>,
+}
+
+// Keeps track of the glyphs drawn with each font so the fonts can be subset and embedded
+// once all variables have been drawn
+#[derive(Default)]
+pub struct FontRegistry {
+    fonts: BTreeMap<String, FontUsage>,
+}
+
+impl FontRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_glyphs(
+        &mut self,
+        font_reference_id: &str,
+        face: &'static Face<'static>,
+        glyphs: impl IntoIterator<Item = (u16, String)>,
+    ) {
+        let usage = self
+            .fonts
+            .entry(font_reference_id.to_string())
+            .or_insert_with(|| FontUsage {
+                face,
+                glyphs: BTreeMap::new(),
+            });
+
+        for (glyph_id, text) in glyphs {
+            usage.glyphs.entry(glyph_id).or_insert(text);
+        }
+    }
+
+    // Subset every recorded font down to its used glyphs and reference it on all pages
+    pub fn embed(&self, document: &mut Document) -> Result<(), BoxedError> {
+        for (font_reference_id, usage) in &self.fonts {
+            // .notdef must always be kept
+            let glyph_ids: Vec<u16> = std::iter::once(0)
+                .chain(usage.glyphs.keys().copied())
+                .collect();
+
+            let font_data = subsetter::subset(
+                usage.face.raw_face().data,
+                0,
+                subsetter::Profile::pdf(&glyph_ids),
+            )?;
+            let font_name = format!(
+                "{}+{}",
+                subset_tag(font_reference_id, &glyph_ids),
+                postscript_name(usage.face).unwrap_or(font_reference_id.replace('-', ""))
+            );
+
+            embed_type0_font(
+                document,
+                &font_name,
+                font_reference_id,
+                &font_data,
+                &usage.glyphs,
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+// Subset fonts are named with a six uppercase letter tag, derived from the glyph set so that
+// the same subset always gets the same name
+fn subset_tag(font_reference_id: &str, glyph_ids: &[u16]) -> String {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(font_reference_id.as_bytes());
+    for glyph_id in glyph_ids {
+        hasher.update(&glyph_id.to_be_bytes());
+    }
+
+    let mut hash = hasher.finalize();
+    (0..6)
+        .map(|_| {
+            let letter = (b'A' + (hash % 26) as u8) as char;
+            hash /= 26;
+            letter
+        })
+        .collect()
 }
 
-pub fn reference_base_fonts(document: &mut Document) -> Result<(), BoxedError> {
-    let helvetica_font_id = create_font(document, "Helvetica");
-    let courier_font_id = create_font(document, "Courier");
-    let times_font_id = create_font(document, "Times New Roman");
+fn postscript_name(face: &ttf_parser::Face) -> Option<String> {
+    face.names()
+        .into_iter()
+        .filter(|name| name.name_id == ttf_parser::name_id::POST_SCRIPT_NAME)
+        .find_map(|name| name.to_string())
+}
 
+// Register a font object under `font_reference_id` in the resources of every page
+fn add_font_to_pages(
+    document: &mut Document,
+    font_reference_id: &str,
+    font_id: (u32, u16),
+) -> Result<(), BoxedError> {
     for (_, page_id) in document.get_pages() {
         let mut resources_dict = {
             document
@@ -32,23 +116,21 @@ pub fn reference_base_fonts(document: &mut Document) -> Result<(), BoxedError> {
                 .clone()
         };
 
-        let font_entry = resources_dict.get_mut(b"Font");
-
-        let font_obj: &mut Object = if let Ok(entry) = font_entry {
-            entry
-        } else {
-            resources_dict.set(b"Font", Object::Dictionary(Dictionary::new()));
-            resources_dict.get_mut(b"Font")?
+        let mut font_dict = match resources_dict.get(b"Font") {
+            Ok(Object::Reference(font_dict_id)) => {
+                document.get_object(*font_dict_id)?.as_dict()?.clone()
+            }
+            Ok(Object::Dictionary(font_dict)) => font_dict.clone(),
+            _ => Dictionary::new(),
         };
-        let font_dict = font_obj.as_dict_mut()?;
 
-        font_dict.set("pdf-Serif", times_font_id);
-        font_dict.set("pdf-SansSerif", helvetica_font_id);
-        font_dict.set("pdf-Mono", courier_font_id);
+        font_dict.set(font_reference_id, font_id);
+        resources_dict.set("Font", Object::Dictionary(font_dict));
+
         document
             .get_object_mut(page_id)?
             .as_dict_mut()?
-            .set("Resources", resources_dict.clone());
+            .set("Resources", resources_dict);
     }
 
     Ok(())
@@ -66,7 +148,7 @@ pub fn get_most_used_font_size(document: &Document) -> f32 {
                 && let Ok(content) = Content::decode(decompressed.as_bytes())
             {
                 for operation in content.operations {
-                    if operation.operator == "Tf" && operation.operands.len() > 0 {
+                    if operation.operator == "Tf" && !operation.operands.is_empty() {
                         current_size = operation
                             .operands
                             .get(1)
@@ -75,16 +157,17 @@ pub fn get_most_used_font_size(document: &Document) -> f32 {
                             .unwrap_or(current_size);
                     }
 
-                    if operation.operator == "Tj" && operation.operands.len() == 1 {
-                        if let Some(operand) = operation.operands.first() {
-                            let str = operand.as_str().unwrap_or(&[]);
-                            let size = (current_size * current_scale).to_string();
-                            let len = str.len();
-                            size_map
-                                .entry(size)
-                                .and_modify(|x| *x += len)
-                                .or_insert(len);
-                        }
+                    if operation.operator == "Tj"
+                        && operation.operands.len() == 1
+                        && let Some(operand) = operation.operands.first()
+                    {
+                        let str = operand.as_str().unwrap_or(&[]);
+                        let size = (current_size * current_scale).to_string();
+                        let len = str.len();
+                        size_map
+                            .entry(size)
+                            .and_modify(|x| *x += len)
+                            .or_insert(len);
                     }
                     if operation.operator == "TJ"
                         && operation.operands.len() == 1
@@ -118,186 +201,356 @@ pub fn get_most_used_font_size(document: &Document) -> f32 {
         .unwrap_or(11.0f32)
 }
 
+// Character codes 128-159 of WinAnsiEncoding (NUL marks unused codes), codes 160-255 match
+// Latin-1
+const WIN_ANSI_HIGH: &str = "€\0‚ƒ„…†‡ˆ‰Š‹Œ\0Ž\0\0‘’“”•–—˜™š›œ\0žŸ";
+
+fn win_ansi_char(code: u8) -> Option<char> {
+    match code {
+        128..=159 => WIN_ANSI_HIGH
+            .chars()
+            .nth(code as usize - 128)
+            .filter(|ch| *ch != '\0'),
+        _ => Some(code as char),
+    }
+}
+
+// Embed a TrueType font as a simple font using WinAnsiEncoding. The descriptor and widths
+// are read from the font itself.
 pub fn embed_ttf_font(
     document: &mut Document,
     font_name: &str,
     font_reference_id: &str,
-    font_data: &'static [u8],
+    font_data: &[u8],
 ) -> Result<(), BoxedError> {
-    let font_stream_id = document.new_object_id();
-    let font_stream = Stream::new(
-        lopdf::dictionary! {
-            "Length" => font_data.len() as i64,
+    let face = ttf_parser::Face::parse(font_data, 0)?;
+
+    let font_stream_id = document.add_object(Stream::new(
+        dictionary! {
             "Length1" => font_data.len() as i64, // TrueType needs Length1
         },
         font_data.to_vec(),
+    ));
+
+    let font_descriptor_id = document.add_object(font_descriptor(&face, font_name, font_stream_id));
+
+    let first_char = 32u8;
+    let last_char = 255u8;
+    let widths: Vec<Object> = (first_char..=last_char)
+        .map(|code| {
+            let advance = win_ansi_char(code)
+                .and_then(|ch| face.glyph_index(ch))
+                .and_then(|glyph_id| face.glyph_hor_advance(glyph_id))
+                .unwrap_or(0);
+            Object::Integer(to_glyph_space(&face, advance as f32))
+        })
+        .collect();
+
+    let font_id = document.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "TrueType",
+        "BaseFont" => Object::Name(font_name.as_bytes().to_vec()),
+        "FontDescriptor" => font_descriptor_id,
+        "Encoding" => "WinAnsiEncoding",
+        "FirstChar" => first_char as i64,
+        "LastChar" => last_char as i64,
+        "Widths" => Object::Array(widths),
+    });
+
+    add_font_to_pages(document, font_reference_id, font_id)
+}
+
+// Scale a value in font units to the 1000 unit glyph space used by PDF font dictionaries
+fn to_glyph_space(face: &ttf_parser::Face, value: f32) -> i64 {
+    (value * 1000.0 / face.units_per_em() as f32).round() as i64
+}
+
+fn font_descriptor(
+    face: &ttf_parser::Face,
+    font_name: &str,
+    font_file_id: (u32, u16),
+) -> Dictionary {
+    let bbox = face.global_bounding_box();
+
+    let mut flags = 32; // Nonsymbolic
+    if face.is_monospaced() {
+        flags |= 1; // FixedPitch
+    }
+    if face.is_italic() {
+        flags |= 64; // Italic
+    }
+
+    dictionary! {
+        "Type" => "FontDescriptor",
+        "FontName" => Object::Name(font_name.as_bytes().to_vec()),
+        "Flags" => flags,
+        "FontBBox" => Object::Array(vec![
+            to_glyph_space(face, bbox.x_min as f32).into(),
+            to_glyph_space(face, bbox.y_min as f32).into(),
+            to_glyph_space(face, bbox.x_max as f32).into(),
+            to_glyph_space(face, bbox.y_max as f32).into(),
+        ]),
+        "ItalicAngle" => face.italic_angle(),
+        "Ascent" => to_glyph_space(face, face.ascender() as f32),
+        "Descent" => to_glyph_space(face, face.descender() as f32),
+        "CapHeight" => to_glyph_space(
+            face,
+            face.capital_height().unwrap_or(face.ascender()) as f32,
+        ),
+        "XHeight" => to_glyph_space(face, face.x_height().unwrap_or(0) as f32),
+        "FontWeight" => face.weight().to_number() as i64,
+        // No stem width is stored in TrueType fonts, approximate it from the weight class
+        "StemV" => 10 + 220 * (face.weight().to_number() as i64 - 50).max(0) / 900,
+        "FontFile2" => font_file_id,
+    }
+}
+
+// Build a ToUnicode CMap mapping each glyph back to the text it was shaped from so text drawn
+// with glyph IDs stays searchable and copyable
+fn to_unicode_cmap(glyphs: &BTreeMap<u16, String>) -> Vec<u8> {
+    let mut cmap = String::from(
+        "/CIDInit /ProcSet findresource begin\n\
+         12 dict begin\n\
+         begincmap\n\
+         /CIDSystemInfo << /Registry (Adobe) /Ordering (UCS) /Supplement 0 >> def\n\
+         /CMapName /Adobe-Identity-UCS def\n\
+         /CMapType 2 def\n\
+         1 begincodespacerange\n\
+         <0000> <FFFF>\n\
+         endcodespacerange\n",
     );
-    document
-        .objects
-        .insert(font_stream_id, Object::Stream(font_stream));
-
-    let font_descriptor_id = document.new_object_id();
-    document.objects.insert(
-        font_descriptor_id,
-        Object::Dictionary(lopdf::dictionary! {
-            "Type" => "FontDescriptor",
-            "FontName" => lopdf::Object::Name(font_name.as_bytes().to_vec()),
-            "Flags" => 32,
-            "Ascent" => 800,   // dummy values, should come from font metrics
-            "Descent" => -450,
-            "CapHeight" => 577,
-            "ItalicAngle" => 0,
-            "CapHeight" => 800,
-            "AvgWidth" => 419,
-            "MaxWidth" => 1728,
-            "FontWeight" => 400,
-            "StemV" => 41,
-            "XHeight" => 250,
-            "FontBBox" => lopdf::Object::Array(vec![
-                lopdf::Object::Integer(-464),
-                lopdf::Object::Integer(-450),
-                lopdf::Object::Integer(1264),
-                lopdf::Object::Integer(800),
-            ]),
-            "FontFile2" => font_stream_id,
-        }),
-    );
 
-    let font_id = document.new_object_id();
-    document.objects.insert(
-        font_id,
-        Object::Dictionary(lopdf::dictionary! {
-            "Type" => "Font",
-            "Subtype" => "TrueType",
-            "Name" => format!("{font_reference_id}"),
-            "BaseFont" => lopdf::Object::Name(font_name.as_bytes().to_vec()),
-            "FontDescriptor" => font_descriptor_id,
-            "Encoding" => "WinAnsiEncoding",
-            "FirstChar" => 32,
-            "LastChar" => 126,
-            "Widths" => lopdf::Object::Array(vec![
-                lopdf::Object::Integer(169),
-                lopdf::Object::Integer(295),
-                lopdf::Object::Integer(168),
-                lopdf::Object::Integer(579),
-                lopdf::Object::Integer(313),
-                lopdf::Object::Integer(632),
-                lopdf::Object::Integer(608),
-                lopdf::Object::Integer(106),
-                lopdf::Object::Integer(268),
-                lopdf::Object::Integer(507),
-                lopdf::Object::Integer(507),
-                lopdf::Object::Integer(326),
-                lopdf::Object::Integer(129),
-                lopdf::Object::Integer(249),
-                lopdf::Object::Integer(149),
-                lopdf::Object::Integer(377),
-                lopdf::Object::Integer(368),
-                lopdf::Object::Integer(243),
-                lopdf::Object::Integer(331),
-                lopdf::Object::Integer(314),
-                lopdf::Object::Integer(403),
-                lopdf::Object::Integer(331),
-                lopdf::Object::Integer(336),
-                lopdf::Object::Integer(310),
-                lopdf::Object::Integer(351),
-                lopdf::Object::Integer(336),
-                lopdf::Object::Integer(154),
-                lopdf::Object::Integer(154),
-                lopdf::Object::Integer(293),
-                lopdf::Object::Integer(344),
-                lopdf::Object::Integer(293),
-                lopdf::Object::Integer(434),
-                lopdf::Object::Integer(707),
-                lopdf::Object::Integer(768),
-                lopdf::Object::Integer(562),
-                lopdf::Object::Integer(527),
-                lopdf::Object::Integer(626),
-                lopdf::Object::Integer(478),
-                lopdf::Object::Integer(582),
-                lopdf::Object::Integer(576),
-                lopdf::Object::Integer(770),
-                lopdf::Object::Integer(365),
-                lopdf::Object::Integer(203),
-                lopdf::Object::Integer(560),
-                lopdf::Object::Integer(547),
-                lopdf::Object::Integer(830),
-                lopdf::Object::Integer(721),
-                lopdf::Object::Integer(594),
-                lopdf::Object::Integer(541),
-                lopdf::Object::Integer(599),
-                lopdf::Object::Integer(602),
-                lopdf::Object::Integer(451),
-                lopdf::Object::Integer(432),
-                lopdf::Object::Integer(611),
-                lopdf::Object::Integer(538),
-                lopdf::Object::Integer(892),
-                lopdf::Object::Integer(542),
-                lopdf::Object::Integer(452),
-                lopdf::Object::Integer(453),
-                lopdf::Object::Integer(362),
-                lopdf::Object::Integer(498),
-                lopdf::Object::Integer(397),
-                lopdf::Object::Integer(313),
-                lopdf::Object::Integer(846),
-                lopdf::Object::Integer(223),
-                lopdf::Object::Integer(294),
-                lopdf::Object::Integer(269),
-                lopdf::Object::Integer(238),
-                lopdf::Object::Integer(294),
-                lopdf::Object::Integer(249),
-                lopdf::Object::Integer(182),
-                lopdf::Object::Integer(265),
-                lopdf::Object::Integer(294),
-                lopdf::Object::Integer(192),
-                lopdf::Object::Integer(135),
-                lopdf::Object::Integer(324),
-                lopdf::Object::Integer(214),
-                lopdf::Object::Integer(470),
-                lopdf::Object::Integer(330),
-                lopdf::Object::Integer(265),
-                lopdf::Object::Integer(255),
-                lopdf::Object::Integer(279),
-                lopdf::Object::Integer(272),
-                lopdf::Object::Integer(232),
-                lopdf::Object::Integer(198),
-                lopdf::Object::Integer(393),
-                lopdf::Object::Integer(294),
-                lopdf::Object::Integer(500),
-                lopdf::Object::Integer(299),
-                lopdf::Object::Integer(344),
-                lopdf::Object::Integer(234),
-                lopdf::Object::Integer(354),
-                lopdf::Object::Integer(364),
-                lopdf::Object::Integer(411),
-                lopdf::Object::Integer(277),
-            ])
-        }),
+    // bfchar sections are limited to 100 entries each
+    let entries: Vec<(&u16, &String)> = glyphs
+        .iter()
+        .filter(|(glyph_id, text)| **glyph_id != 0 && !text.is_empty())
+        .collect();
+    for chunk in entries.chunks(100) {
+        cmap.push_str(&format!("{} beginbfchar\n", chunk.len()));
+        for (glyph_id, text) in chunk {
+            let unicode: String = text
+                .encode_utf16()
+                .map(|unit| format!("{unit:04X}"))
+                .collect();
+            cmap.push_str(&format!("<{glyph_id:04X}> <{unicode}>\n"));
+        }
+        cmap.push_str("endbfchar\n");
+    }
+
+    cmap.push_str(
+        "endcmap\n\
+         CMapName currentdict /CMapResource defineresource pop\n\
+         end\n\
+         end\n",
     );
 
-    for (_, page_id) in document.get_pages() {
-        let mut resources_dict = {
-            document
-                .get_or_create_resources(page_id.to_owned())?
-                .as_dict_mut()?
-                .clone()
-        };
+    cmap.into_bytes()
+}
+
+// Embed a TrueType font as a Type0 font with a CIDFontType2 descendant and Identity-H
+// encoding. Text drawn with this font must be written as 2-byte glyph IDs, `glyphs` maps each
+// glyph ID used to the text it represents.
+pub fn embed_type0_font(
+    document: &mut Document,
+    font_name: &str,
+    font_reference_id: &str,
+    font_data: &[u8],
+    glyphs: &BTreeMap<u16, String>,
+) -> Result<(), BoxedError> {
+    let face = ttf_parser::Face::parse(font_data, 0)?;
 
-        let font_entry = resources_dict.get_mut(b"Font");
+    let font_stream_id = document.add_object(Stream::new(
+        dictionary! {
+            "Length1" => font_data.len() as i64,
+        },
+        font_data.to_vec(),
+    ));
 
-        let font_obj: &mut Object = if let Ok(entry) = font_entry {
-            entry
-        } else {
-            resources_dict.set(b"Font", Object::Dictionary(Dictionary::new()));
-            resources_dict.get_mut(b"Font")?
-        };
-        let font_dict = font_obj.as_dict_mut()?;
+    let font_descriptor_id = document.add_object(font_descriptor(&face, font_name, font_stream_id));
 
-        font_dict.set(font_reference_id, font_id);
-        document
-            .get_object_mut(page_id)?
-            .as_dict_mut()?
-            .set("Resources", resources_dict.clone());
+    // Only list widths for the glyphs in use, anything else falls back to DW
+    let mut widths = vec![];
+    for glyph_id in glyphs.keys() {
+        let advance = face
+            .glyph_hor_advance(ttf_parser::GlyphId(*glyph_id))
+            .unwrap_or(0);
+        widths.push(Object::Integer(*glyph_id as i64));
+        widths.push(Object::Array(vec![
+            to_glyph_space(&face, advance as f32).into(),
+        ]));
+    }
+
+    let cid_font_id = document.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "CIDFontType2",
+        "BaseFont" => Object::Name(font_name.as_bytes().to_vec()),
+        "CIDSystemInfo" => dictionary! {
+            "Registry" => Object::string_literal("Adobe"),
+            "Ordering" => Object::string_literal("Identity"),
+            "Supplement" => 0,
+        },
+        "FontDescriptor" => font_descriptor_id,
+        "W" => Object::Array(widths),
+        "CIDToGIDMap" => "Identity",
+    });
+
+    let to_unicode_id = document.add_object(Stream::new(dictionary! {}, to_unicode_cmap(glyphs)));
+
+    let font_id = document.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type0",
+        "BaseFont" => Object::Name(font_name.as_bytes().to_vec()),
+        "Encoding" => "Identity-H",
+        "DescendantFonts" => Object::Array(vec![cid_font_id.into()]),
+        "ToUnicode" => to_unicode_id,
+    });
+
+    add_font_to_pages(document, font_reference_id, font_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fonts::{CURSIVE_REGULAR_BYTES, FontType, FontWeight, get_font};
+
+    fn document_with_page() -> (Document, (u32, u16)) {
+        let mut document = Document::with_version("1.7");
+        let pages_id = document.new_object_id();
+        let page_id = document.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => vec![0.into(), 0.into(), 200.into(), 100.into()],
+        });
+        document.objects.insert(
+            pages_id,
+            dictionary! {
+                "Type" => "Pages",
+                "Kids" => vec![page_id.into()],
+                "Count" => 1,
+            }
+            .into(),
+        );
+        let catalog_id = document.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+        });
+        document.trailer.set("Root", catalog_id);
+
+        (document, page_id)
+    }
+
+    // Font registered on the page under `font_reference_id`
+    fn page_font<'a>(
+        document: &'a Document,
+        page: (u32, u16),
+        font_reference_id: &str,
+    ) -> &'a Dictionary {
+        let resources = document.get_dictionary(page).unwrap().get(b"Resources");
+        let font_id = resources
+            .unwrap()
+            .as_dict()
+            .unwrap()
+            .get(b"Font")
+            .unwrap()
+            .as_dict()
+            .unwrap()
+            .get(font_reference_id.as_bytes())
+            .unwrap()
+            .as_reference()
+            .unwrap();
+        document.get_dictionary(font_id).unwrap()
+    }
+
+    fn descriptor<'a>(document: &'a Document, font: &Dictionary) -> &'a Dictionary {
+        let id = font.get(b"FontDescriptor").unwrap().as_reference().unwrap();
+        document.get_dictionary(id).unwrap()
+    }
+
+    #[test]
+    fn embed_ttf_font_reads_metrics_from_the_font() {
+        let (mut document, page) = document_with_page();
+        let face = ttf_parser::Face::parse(CURSIVE_REGULAR_BYTES, 0).unwrap();
+
+        embed_ttf_font(&mut document, "Cursive", "F1", CURSIVE_REGULAR_BYTES).unwrap();
+
+        let font = page_font(&document, page, "F1");
+        assert_eq!(
+            font.get(b"Subtype").unwrap().as_name().unwrap(),
+            b"TrueType"
+        );
+        let widths = font.get(b"Widths").unwrap().as_array().unwrap();
+        assert_eq!(widths.len(), 224);
+        let advance = face
+            .glyph_hor_advance(face.glyph_index('A').unwrap())
+            .unwrap();
+        assert_eq!(
+            widths[(b'A' - 32) as usize].as_i64().unwrap(),
+            to_glyph_space(&face, advance as f32)
+        );
+        let descriptor = descriptor(&document, font);
+        assert_eq!(
+            descriptor.get(b"Ascent").unwrap().as_i64().unwrap(),
+            to_glyph_space(&face, face.ascender() as f32)
+        );
+    }
+
+    #[test]
+    fn subset_tags_follow_the_glyph_set() {
+        let tag = subset_tag("pdf-Cursive", &[0, 1, 2]);
+
+        assert_eq!(tag.len(), 6);
+        assert!(tag.chars().all(|ch| ch.is_ascii_uppercase()));
+        assert_eq!(tag, subset_tag("pdf-Cursive", &[0, 1, 2]));
+        assert_ne!(tag, subset_tag("pdf-Cursive", &[0, 1, 3]));
+    }
+
+    #[test]
+    fn to_unicode_maps_glyphs_back_to_their_text() {
+        let glyphs = BTreeMap::from([
+            (0, String::new()),
+            (5, "A".to_string()),
+            (9, "fi".to_string()),
+            (12, String::new()),
+        ]);
+
+        let cmap = String::from_utf8(to_unicode_cmap(&glyphs)).unwrap();
+
+        assert!(cmap.contains("2 beginbfchar\n<0005> <0041>\n<0009> <00660069>\nendbfchar"));
+    }
+
+    #[test]
+    fn registry_embeds_subset_type0_fonts() {
+        let (mut document, page) = document_with_page();
+        let face = get_font(&FontType::Cursive, &FontWeight::Regular, false).face;
+        let glyph_id = face.glyph_index('S').unwrap().0;
+        let mut registry = FontRegistry::new();
+        registry.record_glyphs("pdf-Cursive", face, [(glyph_id, "S".to_string())]);
+
+        registry.embed(&mut document).unwrap();
+
+        let font = page_font(&document, page, "pdf-Cursive");
+        assert_eq!(font.get(b"Subtype").unwrap().as_name().unwrap(), b"Type0");
+        assert_eq!(
+            font.get(b"Encoding").unwrap().as_name().unwrap(),
+            b"Identity-H"
+        );
+        let base_font =
+            String::from_utf8(font.get(b"BaseFont").unwrap().as_name().unwrap().to_vec()).unwrap();
+        assert_eq!(
+            base_font,
+            format!(
+                "{}+{}",
+                subset_tag("pdf-Cursive", &[0, glyph_id]),
+                postscript_name(face).unwrap()
+            )
+        );
+        let to_unicode_id = font.get(b"ToUnicode").unwrap().as_reference().unwrap();
+        let cmap = document
+            .get_object(to_unicode_id)
+            .unwrap()
+            .as_stream()
+            .unwrap();
+        let cmap = String::from_utf8(cmap.content.clone()).unwrap();
+        assert!(cmap.contains(&format!("<{glyph_id:04X}> <0053>")));
     }
-    Ok(())
 }
@@ -8,9 +8,9 @@ use lopdf::{
 };
 use uuid::Uuid;
 
-use crate::error::{BoxedError, GenericError};
+use crate::error::BoxedError;
 
-use super::get_page_media_box;
+use super::get_box_frame;
 
 pub struct DrawImageOptions<'a> {
     pub image_data: &'a Vec<u8>,
@@ -19,6 +19,8 @@ pub struct DrawImageOptions<'a> {
     pub y: f32,
     pub w: f32,
     pub h: f32,
+    // Degrees counterclockwise, the image fills the turned box
+    pub rotation: f32,
 }
 
 pub fn draw_image<'a>(
@@ -26,7 +28,12 @@ pub fn draw_image<'a>(
     page: &(u32, u16),
     options: DrawImageOptions<'a>,
 ) -> Result<(), BoxedError> {
-    let (_, _, _page_w, page_h) = get_page_media_box(document, page)?;
+    let frame = get_box_frame(
+        document,
+        page,
+        (options.x, options.y, options.w, options.h),
+        options.rotation,
+    )?;
 
     let mut resources_dict = document
         .get_or_create_resources(page.to_owned())?
@@ -104,17 +111,18 @@ pub fn draw_image<'a>(
     let content = Content {
         operations: vec![
             Operation::new("q", vec![]), // Save graphics state
+            frame.transform(),           // Into the box
             Operation::new(
                 "cm",
                 vec![
-                    (options.w as f64).into(),
+                    (frame.w as f64).into(),
+                    0.into(),
+                    0.into(),
+                    (frame.h as f64).into(),
                     0.into(),
                     0.into(),
-                    (options.h as f64).into(),
-                    options.x.into(),
-                    (page_h - options.y - options.h).into(), // Position (x, y)
                 ],
-            ),
+            ), // Scale the unit square to the box
             Operation::new("Do", vec![Object::Name(image_uuid.as_bytes().to_vec())]), // Draw image
             Operation::new("Q", vec![]), // Restore graphics state
         ],
@@ -0,0 +1,1400 @@
+use std::ops::Range;
+
+use hypher::Lang;
+use rustybuzz::Feature;
+use unicode_linebreak::{BreakOpportunity, linebreaks};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{
+    error::InvalidOptionError,
+    fonts::{PdfFont, Spacing, is_rtl, paragraph_directions, shape_text_segments},
+    pdf::{Overflow, TabAlignment, TabStop, TextAlignment, VerticalAlign, color::Color},
+};
+
+// Default floor of shrinking text. Text that does not fit at its floor overflows its box.
+pub const MIN_FONT_SIZE: f32 = 1.0;
+
+const ELLIPSIS: &str = "\u{2026}";
+
+// Distance between the stops tabs advance to past the last set one, half an inch
+const TAB_INTERVAL: f32 = 36.0;
+
+// Face, size and color of a range of the text
+#[derive(Clone, Copy)]
+pub struct RunStyle {
+    pub font: PdfFont,
+    // Multiple of the font size of the layout, so styled text shrinks along with the rest
+    pub scale: f32,
+    // The color of the text if unset
+    pub color: Option<Color>,
+}
+
+#[derive(Clone)]
+pub struct StyledRange {
+    pub range: Range<usize>,
+    pub style: RunStyle,
+}
+
+#[derive(Clone)]
+pub struct LayoutOptions {
+    pub font: PdfFont,
+    // Styles of ranges of the text, in order. Text outside of them is set in `font`.
+    pub styles: Vec<StyledRange>,
+    // Size text is drawn at, or shrunk from with `Overflow::Shrink`
+    pub font_size: f32,
+    pub min_font_size: f32,
+    pub overflow: Overflow,
+    // Break lines to the box width. Explicit newlines always break.
+    pub wrap: bool,
+    pub hyphenation: Option<Lang>,
+    pub text_alignment: TextAlignment,
+    pub align_v: VerticalAlign,
+    // Distance between baselines as a multiple of the font size, the face's own line spacing
+    // if unset
+    pub line_height: Option<f32>,
+    pub spacing: Spacing,
+    pub tab_stops: Vec<TabStop>,
+    // OpenType features text is shaped with
+    pub features: Vec<Feature>,
+    pub w: f32,
+    pub h: f32,
+}
+
+// A line of text positioned relative to the top left corner of its box, y pointing down
+#[derive(Clone)]
+pub struct LayoutLine {
+    pub text: String,
+    // Style of each part of the line, by where the part starts in `text`
+    pub segments: Vec<(usize, RunStyle)>,
+    pub x: f32,
+    pub baseline: f32,
+    // Extent of the largest style of the line above and below the baseline
+    pub ascent: f32,
+    pub descent: f32,
+    // Width as painted, a justified line spans the box
+    pub width: f32,
+    // Extra space added to every space of the line, the requested word spacing plus what
+    // justifying the line needs
+    pub word_spacing: f32,
+    // Whether the paragraph of the line runs right to left, the line is reordered in it
+    pub rtl: bool,
+}
+
+#[derive(Clone)]
+pub struct TextLayout {
+    pub font_size: f32,
+    pub lines: Vec<LayoutLine>,
+    // Size of the laid out text, before truncating it with an ellipsis
+    pub width: f32,
+    pub height: f32,
+    // Whether the text, before truncating it with an ellipsis or leaving the rest for another
+    // box, is larger than its box
+    pub overflows: bool,
+}
+
+// Vertical metrics of a line, in points
+#[derive(Clone, Copy)]
+struct LineMetrics {
+    ascent: f32,
+    descent: f32,
+    // Distance to the baselines of the lines next to it: ascent, descent and the face's line gap
+    line_height: f32,
+}
+
+impl LineMetrics {
+    fn new(font: PdfFont, font_size: f32, line_height: Option<f32>) -> Self {
+        let face = font.face;
+        let scale = font_size / face.units_per_em() as f32;
+        let ascent = face.ascender() as f32 * scale;
+        let descent = -(face.descender() as f32) * scale;
+        let line_height = match line_height {
+            Some(line_height) => line_height * font_size,
+            None => ascent + descent + face.line_gap() as f32 * scale,
+        };
+
+        Self {
+            ascent,
+            descent,
+            line_height,
+        }
+    }
+
+    // Metrics of a line mixing both
+    fn max(self, other: LineMetrics) -> Self {
+        Self {
+            ascent: self.ascent.max(other.ascent),
+            descent: self.descent.max(other.descent),
+            line_height: self.line_height.max(other.line_height),
+        }
+    }
+}
+
+// Baseline of each line from the ascender of the first. Lines are spaced by the larger line
+// height of the two, so uniform text is spaced evenly.
+fn baselines(metrics: &[LineMetrics]) -> Vec<f32> {
+    let mut baseline = metrics.first().map_or(0.0, |first| first.ascent);
+    let mut above: Option<&LineMetrics> = None;
+
+    metrics
+        .iter()
+        .map(|line| {
+            if let Some(above) = above {
+                baseline += above.line_height.max(line.line_height);
+            }
+            above = Some(line);
+            baseline
+        })
+        .collect()
+}
+
+// Height from the ascender of the first line to the descender of the last
+fn block_height(metrics: &[LineMetrics]) -> f32 {
+    match (baselines(metrics).last(), metrics.last()) {
+        (Some(baseline), Some(last)) => baseline + last.descent,
+        _ => 0.0,
+    }
+}
+
+// Style of the segment `offset` falls in
+pub fn style_of(segments: &[(usize, RunStyle)], offset: usize) -> RunStyle {
+    segments
+        .iter()
+        .rev()
+        .find(|(start, _)| *start <= offset)
+        .or(segments.first())
+        .map(|(_, style)| *style)
+        .expect("Line without segments")
+}
+
+// Advances of the glyphs of a text shaped once, each paragraph in its own direction, summed up
+// to every byte. Glyphs grow in proportion to the font size and spacing adds the same at every
+// size, so parts of the text are measured at any size without shaping them again.
+struct Advances<'a> {
+    text: &'a str,
+    // Before each byte: advance in ems, scaled by the style of the glyph, advancing glyphs and
+    // glyphs of spaces
+    ems: Vec<f64>,
+    glyphs: Vec<u32>,
+    spaces: Vec<u32>,
+    // Whether a cluster starts at each byte. Parts of the text are only measured from the sums
+    // between clusters.
+    cluster_starts: Vec<bool>,
+}
+
+impl<'a> Advances<'a> {
+    // No source text, everything measured is shaped on its own
+    fn unshaped() -> Self {
+        Self {
+            text: "",
+            ems: vec![0.0],
+            glyphs: vec![0],
+            spaces: vec![0],
+            cluster_starts: vec![true],
+        }
+    }
+
+    fn new(text: &'a str, options: &LayoutOptions) -> Self {
+        let unshaped = Advances::unshaped();
+        let measure = measure(options, &unshaped, 1.0);
+        let mut ems = vec![0.0; text.len() + 1];
+        let mut glyphs = vec![0; text.len() + 1];
+        let mut spaces = vec![0; text.len() + 1];
+        let mut cluster_starts = vec![false; text.len() + 1];
+        cluster_starts[text.len()] = true;
+
+        for (range, rtl) in paragraph_directions(text) {
+            let segments = measure.segments(range.start, range.len());
+            let fonts: Vec<(usize, PdfFont)> = segments
+                .iter()
+                .map(|(offset, style)| (*offset, style.font))
+                .collect();
+
+            let paragraph = &text[range.clone()];
+            let runs = shape_text_segments(paragraph, &fonts, measure.features, Some(rtl));
+            for run in runs {
+                let scale = style_of(&segments, run.offset).scale as f64
+                    / run.font.face.units_per_em() as f64;
+                let buffer = &run.glyph_buffer;
+                for (info, pos) in buffer.glyph_infos().iter().zip(buffer.glyph_positions()) {
+                    let cluster = info.cluster as usize;
+                    // Sums run to the byte after the cluster start
+                    let index = range.start + run.offset + cluster;
+                    ems[index + 1] += pos.x_advance as f64 * scale;
+                    if pos.x_advance != 0 {
+                        glyphs[index + 1] += 1;
+                    }
+                    if run.text[cluster..].starts_with(' ') {
+                        spaces[index + 1] += 1;
+                    }
+                    cluster_starts[index] = true;
+                }
+            }
+        }
+
+        for index in 1..=text.len() {
+            ems[index] += ems[index - 1];
+            glyphs[index] += glyphs[index - 1];
+            spaces[index] += spaces[index - 1];
+        }
+
+        Self {
+            text,
+            ems,
+            glyphs,
+            spaces,
+            cluster_starts,
+        }
+    }
+
+    // Length of the start of `text` that is the source from `start` on, up to a cluster start
+    fn source_len(&self, text: &str, start: usize) -> usize {
+        if self.cluster_starts.get(start) != Some(&true) {
+            return 0;
+        }
+
+        let mut len = text
+            .bytes()
+            .zip(self.text[start..].bytes())
+            .take_while(|(a, b)| a == b)
+            .count();
+        while !self.cluster_starts[start + len] {
+            len -= 1;
+        }
+        len
+    }
+
+    fn width(&self, range: Range<usize>, font_size: f32, spacing: Spacing) -> f32 {
+        let ems = self.ems[range.end] - self.ems[range.start];
+        let glyphs = self.glyphs[range.end] - self.glyphs[range.start];
+        let spaces = self.spaces[range.end] - self.spaces[range.start];
+
+        ems as f32 * font_size + glyphs as f32 * spacing.letter + spaces as f32 * spacing.word
+    }
+}
+
+// Measures text the way it is painted. Text is measured from where it starts in the source, so
+// the styles of its ranges apply.
+struct Measure<'a> {
+    font: PdfFont,
+    styles: &'a [StyledRange],
+    font_size: f32,
+    line_height: Option<f32>,
+    spacing: Spacing,
+    tab_stops: &'a [TabStop],
+    features: &'a [Feature],
+    advances: &'a Advances<'a>,
+}
+
+// The part of a line between two tabs, placed at the stop the tab before it advances to
+struct TabCell {
+    // Bytes of the line
+    range: Range<usize>,
+    x: f32,
+    width: f32,
+}
+
+impl Measure<'_> {
+    // Style of a character of the source. Characters added to lines past the end of the source,
+    // like hyphens and ellipses, take the style of the last range.
+    fn style_at(&self, index: usize) -> RunStyle {
+        let base = RunStyle {
+            font: self.font,
+            scale: 1.0,
+            color: None,
+        };
+
+        match self
+            .styles
+            .iter()
+            .find(|styled| styled.range.contains(&index))
+        {
+            Some(styled) => styled.style,
+            None => match self.styles.last() {
+                Some(last) if index >= last.range.end => last.style,
+                _ => base,
+            },
+        }
+    }
+
+    // Styles of the parts of `len` bytes of the source from `start`, by where they start
+    fn segments(&self, start: usize, len: usize) -> Vec<(usize, RunStyle)> {
+        let mut boundaries: Vec<usize> = self
+            .styles
+            .iter()
+            .flat_map(|styled| [styled.range.start, styled.range.end])
+            .filter(|boundary| *boundary > start && *boundary < start + len)
+            .map(|boundary| boundary - start)
+            .collect();
+        boundaries.insert(0, 0);
+        boundaries.dedup();
+
+        boundaries
+            .into_iter()
+            .map(|boundary| (boundary, self.style_at(start + boundary)))
+            .collect()
+    }
+
+    // Width of text without tabs. As much of it as is the source is measured from the advances
+    // of the source, the rest, like a hyphen or an ellipsis, is shaped.
+    fn shaped_width(&self, text: &str, start: usize) -> f32 {
+        let len = self.advances.source_len(text, start);
+        if len == 0 {
+            return self.shape_width(text, start);
+        }
+
+        let width = self
+            .advances
+            .width(start..start + len, self.font_size, self.spacing);
+        if len == text.len() {
+            return width;
+        }
+
+        width + self.shape_width(&text[len..], start + len)
+    }
+
+    // Width of text without tabs, shaped on its own
+    fn shape_width(&self, text: &str, start: usize) -> f32 {
+        let segments = self.segments(start, text.len());
+        let fonts: Vec<(usize, PdfFont)> = segments
+            .iter()
+            .map(|(offset, style)| (*offset, style.font))
+            .collect();
+
+        shape_text_segments(text, &fonts, self.features, None)
+            .iter()
+            .map(|run| {
+                let scale = style_of(&segments, run.offset).scale;
+                run.width(self.font_size * scale, self.spacing)
+            })
+            .sum()
+    }
+
+    // Width of a line from its start, with the text after each tab moved to its stop
+    fn width(&self, text: &str, start: usize) -> f32 {
+        if !text.contains('\t') {
+            return self.shaped_width(text, start);
+        }
+
+        self.tab_cells(text, start)
+            .last()
+            .map_or(0.0, |cell| cell.x + cell.width)
+    }
+
+    // First stop right of `x`
+    fn tab_stop(&self, x: f32) -> TabStop {
+        self.tab_stops
+            .iter()
+            .filter(|stop| stop.position > x)
+            .min_by(|a, b| a.position.total_cmp(&b.position))
+            .copied()
+            .unwrap_or(TabStop {
+                position: ((x / TAB_INTERVAL).floor() + 1.0) * TAB_INTERVAL,
+                align: None,
+            })
+    }
+
+    // Split a line at its tabs and place each part at the stop its tab advances to
+    fn tab_cells(&self, line: &str, start: usize) -> Vec<TabCell> {
+        let mut cells: Vec<TabCell> = vec![];
+        let mut offset = 0;
+
+        for cell in line.split('\t') {
+            let width = self.shaped_width(cell, start + offset);
+            let x = match cells.last() {
+                None => 0.0,
+                Some(previous) => {
+                    let end = previous.x + previous.width;
+                    let stop = self.tab_stop(end);
+                    let anchor = match stop.align.unwrap_or_default() {
+                        TabAlignment::Left => 0.0,
+                        TabAlignment::Right => width,
+                        TabAlignment::Center => width / 2.0,
+                        TabAlignment::Decimal => cell
+                            .match_indices('.')
+                            .map(|(point, _)| point)
+                            .find(|point| {
+                                cell[point + 1..].starts_with(|ch: char| ch.is_ascii_digit())
+                            })
+                            .map_or(width, |point| {
+                                self.shaped_width(&cell[..point], start + offset)
+                            }),
+                    };
+                    // Text never moves back over the part before it
+                    (stop.position - anchor).max(end)
+                }
+            };
+
+            cells.push(TabCell {
+                range: offset..offset + cell.len(),
+                x,
+                width,
+            });
+            offset += cell.len() + 1;
+        }
+
+        cells
+    }
+
+    fn line_metrics(&self, line: &BrokenLine) -> LineMetrics {
+        self.segments(line.start, line.text.len())
+            .into_iter()
+            .map(|(_, style)| {
+                LineMetrics::new(style.font, self.font_size * style.scale, self.line_height)
+            })
+            .reduce(LineMetrics::max)
+            .expect("Line without segments")
+    }
+}
+
+struct BrokenLine {
+    text: String,
+    // Where in the source text the line starts
+    start: usize,
+    // Whether the line ends a paragraph, either at an explicit newline or at the end of the text
+    paragraph_end: bool,
+    // Where in the source text the next line starts
+    next: usize,
+}
+
+// Hyphenation language from its ISO 639-1 code, given for the variable `field`
+pub fn hyphenation_lang(field: &str, code: &str) -> Result<Lang, InvalidOptionError> {
+    <[u8; 2]>::try_from(code.to_ascii_lowercase().as_bytes())
+        .ok()
+        .and_then(Lang::from_iso)
+        .ok_or(InvalidOptionError {
+            field: field.to_string(),
+            message: format!("Unsupported hyphenation language: {code}"),
+        })
+}
+
+fn is_newline(ch: char) -> bool {
+    matches!(
+        ch,
+        '\n' | '\r' | '\u{0B}' | '\u{0C}' | '\u{85}' | '\u{2028}' | '\u{2029}'
+    )
+}
+
+// Split the word at the start of `segment` at the last syllable boundary that still lets `line`
+// plus the first part and a hyphen fit in `max_width`. Returns the hyphenated line and the rest
+// of the segment.
+fn hyphenate_to_fit<'a>(
+    measure: &Measure,
+    line: &str,
+    line_start: usize,
+    segment: &'a str,
+    max_width: f32,
+    lang: Lang,
+) -> Option<(String, &'a str)> {
+    let mut boundaries: Vec<usize> = hypher::hyphenate(segment.trim_end(), lang)
+        .scan(0, |end, syllable| {
+            *end += syllable.len();
+            Some(*end)
+        })
+        .collect();
+    // The last boundary is the end of the word
+    boundaries.pop();
+
+    boundaries.into_iter().rev().find_map(|boundary| {
+        let head = &segment[..boundary];
+        let hyphenated = if head.ends_with('-') {
+            format!("{line}{head}")
+        } else {
+            format!("{line}{head}-")
+        };
+
+        (measure.width(&hyphenated, line_start) <= max_width)
+            .then(|| (hyphenated, &segment[boundary..]))
+    })
+}
+
+// Split a word wider than the box after the last grapheme that fits, keeping at least one
+// grapheme on the line
+fn split_to_fit<'a>(
+    measure: &Measure,
+    word: &'a str,
+    word_start: usize,
+    max_width: f32,
+) -> (&'a str, &'a str) {
+    let mut split = 0;
+    for (index, grapheme) in word.grapheme_indices(true) {
+        let end = index + grapheme.len();
+        if split > 0 && measure.width(&word[..end], word_start) > max_width {
+            break;
+        }
+        split = end;
+    }
+
+    word.split_at(split)
+}
+
+// Where the line after `lines` starts in the source, lines follow each other without gaps
+fn line_start(lines: &[BrokenLine]) -> usize {
+    lines.last().map_or(0, |line| line.next)
+}
+
+// Add a segment (a word and the spaces after it) to the line being built, moving on to new lines
+// as it overflows `max_width`
+fn place_segment(
+    lines: &mut Vec<BrokenLine>,
+    current: &mut String,
+    segment: &str,
+    offset: usize,
+    measure: &Measure,
+    max_width: f32,
+    hyphenation: Option<Lang>,
+) {
+    // `rest` is always the end of `segment`
+    let offset_of = |rest: &str| offset + segment.len() - rest.len();
+
+    let mut rest = segment;
+    loop {
+        let start = line_start(lines);
+
+        // Trailing spaces never overflow a line
+        let line = format!("{current}{rest}");
+        if rest.trim_end().is_empty() || measure.width(line.trim_end(), start) <= max_width {
+            *current = line;
+            return;
+        }
+
+        if let Some((line, tail)) = hyphenation
+            .and_then(|lang| hyphenate_to_fit(measure, current, start, rest, max_width, lang))
+        {
+            lines.push(BrokenLine {
+                text: line,
+                start,
+                paragraph_end: false,
+                next: offset_of(tail),
+            });
+            current.clear();
+            rest = tail;
+            continue;
+        }
+
+        if !current.is_empty() {
+            lines.push(BrokenLine {
+                text: current.trim_end().to_string(),
+                start,
+                paragraph_end: false,
+                next: offset_of(rest),
+            });
+            current.clear();
+            continue;
+        }
+
+        let (head, tail) = split_to_fit(measure, rest, start, max_width);
+        lines.push(BrokenLine {
+            text: head.to_string(),
+            start,
+            paragraph_end: false,
+            next: offset_of(tail),
+        });
+        rest = tail;
+    }
+}
+
+// Break text into lines at the line break opportunities of Unicode Standard Annex #14. Explicit
+// newlines always end a line. With a `max_width`, lines are also broken so they fit it, either
+// between words, at a syllable of the overflowing word when hyphenating, or between graphemes
+// for words wider than `max_width` on their own.
+fn break_lines(
+    measure: &Measure,
+    text: &str,
+    max_width: Option<f32>,
+    hyphenation: Option<Lang>,
+) -> Vec<BrokenLine> {
+    let mut lines = vec![];
+    let mut current = String::new();
+    let mut start = 0;
+
+    for (end, opportunity) in linebreaks(text) {
+        let mandatory = opportunity == BreakOpportunity::Mandatory;
+        let segment = &text[start..end];
+        let segment = if mandatory {
+            segment.trim_end_matches(is_newline)
+        } else {
+            segment
+        };
+
+        match max_width {
+            Some(max_width) => place_segment(
+                &mut lines,
+                &mut current,
+                segment,
+                start,
+                measure,
+                max_width,
+                hyphenation,
+            ),
+            None => current.push_str(segment),
+        }
+        start = end;
+
+        if mandatory {
+            lines.push(BrokenLine {
+                text: current.trim_end().to_string(),
+                start: line_start(&lines),
+                paragraph_end: true,
+                next: end,
+            });
+            current.clear();
+        }
+    }
+
+    lines
+}
+
+// Offset of a line from the left edge of its box, and the extra space added to each of its
+// word gaps. Justified text fills the box on every line but the last of a paragraph, which
+// keeps the natural alignment of the paragraph direction, right to left if `rtl`.
+fn align_line(
+    alignment: &TextAlignment,
+    line: &str,
+    line_width: f32,
+    box_width: f32,
+    paragraph_end: bool,
+    rtl: bool,
+) -> (f32, f32) {
+    match alignment {
+        TextAlignment::Left => (0.0, 0.0),
+        TextAlignment::Center => ((box_width - line_width) / 2.0, 0.0),
+        TextAlignment::Right => (box_width - line_width, 0.0),
+        TextAlignment::Justify => {
+            let gaps = line.matches(' ').count();
+            if paragraph_end || gaps == 0 || line_width >= box_width {
+                if rtl {
+                    (box_width - line_width, 0.0)
+                } else {
+                    (0.0, 0.0)
+                }
+            } else {
+                (0.0, (box_width - line_width) / gaps as f32)
+            }
+        }
+    }
+}
+
+fn measure<'a>(
+    options: &'a LayoutOptions,
+    advances: &'a Advances<'a>,
+    font_size: f32,
+) -> Measure<'a> {
+    Measure {
+        font: options.font,
+        styles: &options.styles,
+        font_size,
+        line_height: options.line_height,
+        spacing: options.spacing,
+        tab_stops: &options.tab_stops,
+        features: &options.features,
+        advances,
+    }
+}
+
+fn break_lines_at(text: &str, options: &LayoutOptions, measure: &Measure) -> Vec<BrokenLine> {
+    break_lines(
+        measure,
+        text,
+        options.wrap.then_some(options.w),
+        options.hyphenation,
+    )
+}
+
+fn line_metrics(lines: &[BrokenLine], measure: &Measure) -> Vec<LineMetrics> {
+    lines
+        .iter()
+        .map(|line| measure.line_metrics(line))
+        .collect()
+}
+
+// Width and height of broken lines
+fn block_size(lines: &[BrokenLine], measure: &Measure) -> (f32, f32) {
+    let width = lines
+        .iter()
+        .map(|line| measure.width(&line.text, line.start))
+        .fold(0f32, f32::max);
+
+    (width, block_height(&line_metrics(lines, measure)))
+}
+
+// Number of lines, at least one, whose block fits `max_height`
+fn fitting_line_count(metrics: &[LineMetrics], max_height: f32) -> usize {
+    (1..=metrics.len())
+        .rev()
+        .find(|count| block_height(&metrics[..*count]) <= max_height)
+        .unwrap_or(1)
+        .min(metrics.len())
+}
+
+fn fits(advances: &Advances, options: &LayoutOptions, font_size: f32) -> bool {
+    let measure = measure(options, advances, font_size);
+    let lines = break_lines_at(advances.text, options, &measure);
+    let (width, height) = block_size(&lines, &measure);
+
+    width <= options.w && height <= options.h
+}
+
+// Binary search for the largest size between the floor and the requested size at which the text
+// of `advances` fits the box. Only line breaking is repeated at each size.
+fn fit_font_size(advances: &Advances, options: &LayoutOptions) -> f32 {
+    let mut min = options.min_font_size.min(options.font_size);
+    let mut max = options.font_size;
+    if fits(advances, options, max) {
+        return max;
+    }
+
+    while max - min > 0.1 {
+        let size = (min + max) / 2.0;
+        if fits(advances, options, size) {
+            min = size;
+        } else {
+            max = size;
+        }
+    }
+
+    min
+}
+
+// Longest start of `text` that still fits `max_width` with an ellipsis after it
+fn with_ellipsis(measure: &Measure, text: &str, start: usize, max_width: f32) -> String {
+    let mut ends: Vec<usize> = text
+        .grapheme_indices(true)
+        .map(|(index, _)| index)
+        .collect();
+    ends.push(text.len());
+
+    ends.into_iter()
+        .rev()
+        .map(|end| format!("{}{ELLIPSIS}", text[..end].trim_end()))
+        .find(|line| measure.width(line, start) <= max_width)
+        .unwrap_or(ELLIPSIS.to_string())
+}
+
+// Drop the lines below the box and end the last line kept, and every line wider than the box,
+// with an ellipsis
+fn truncate_lines(
+    mut lines: Vec<BrokenLine>,
+    options: &LayoutOptions,
+    measure: &Measure,
+) -> Vec<BrokenLine> {
+    let line_count = fitting_line_count(&line_metrics(&lines, measure), options.h);
+    let truncated = lines.len() > line_count;
+    lines.truncate(line_count);
+
+    for (index, line) in lines.iter_mut().enumerate() {
+        let last = index + 1 == line_count;
+        if (last && truncated) || measure.width(&line.text, line.start) > options.w {
+            line.text = with_ellipsis(measure, &line.text, line.start, options.w);
+            line.paragraph_end = true;
+        }
+    }
+
+    lines
+}
+
+// Whether the paragraph the source offset falls in runs right to left. Offsets past the end of
+// the text belong to the last paragraph.
+fn rtl_at(directions: &[(Range<usize>, bool)], offset: usize) -> bool {
+    directions
+        .iter()
+        .find(|(range, _)| range.contains(&offset))
+        .or(directions.last())
+        .is_some_and(|(_, rtl)| *rtl)
+}
+
+// Position lines broken from `text` in the box
+fn position_lines(
+    text: &str,
+    lines: Vec<BrokenLine>,
+    options: &LayoutOptions,
+    measure: &Measure,
+) -> Vec<LayoutLine> {
+    let directions = paragraph_directions(text);
+    let metrics = line_metrics(&lines, measure);
+    let baselines = baselines(&metrics);
+    let total_height = block_height(&metrics);
+    let top = match options.align_v {
+        VerticalAlign::Top => 0.0,
+        VerticalAlign::Middle => (options.h - total_height) / 2.0,
+        VerticalAlign::Bottom => options.h - total_height,
+    };
+
+    lines
+        .into_iter()
+        .zip(metrics)
+        .zip(baselines)
+        .flat_map(|((line, line_metrics), baseline)| {
+            let rtl = rtl_at(&directions, line.start);
+            if !line.text.contains('\t') {
+                let width = measure.width(&line.text, line.start);
+                let (x, word_spacing) = align_line(
+                    &options.text_alignment,
+                    &line.text,
+                    width,
+                    options.w,
+                    line.paragraph_end,
+                    rtl,
+                );
+                return vec![LayoutLine {
+                    segments: measure.segments(line.start, line.text.len()),
+                    x,
+                    baseline: top + baseline,
+                    ascent: line_metrics.ascent,
+                    descent: line_metrics.descent,
+                    width: width + word_spacing * line.text.matches(' ').count() as f32,
+                    word_spacing: options.spacing.word + word_spacing,
+                    rtl,
+                    text: line.text,
+                }];
+            }
+
+            // Each part between tabs is painted as a line of its own at its stop. Stops are measured
+            // from the left edge of the box, so tabbed lines are not aligned.
+            measure
+                .tab_cells(&line.text, line.start)
+                .into_iter()
+                .filter(|cell| !cell.range.is_empty())
+                .map(|cell| LayoutLine {
+                    text: line.text[cell.range.clone()].to_string(),
+                    segments: measure.segments(line.start + cell.range.start, cell.range.len()),
+                    x: cell.x,
+                    baseline: top + baseline,
+                    ascent: line_metrics.ascent,
+                    descent: line_metrics.descent,
+                    width: cell.width,
+                    word_spacing: options.spacing.word,
+                    rtl,
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// Break text into lines and position them in the box, applying the overflow policy
+pub fn layout_text(text: &str, options: &LayoutOptions) -> TextLayout {
+    let advances = Advances::new(text, options);
+    let font_size = match options.overflow {
+        Overflow::Shrink => fit_font_size(&advances, options),
+        _ => options.font_size,
+    };
+
+    let measure = measure(options, &advances, font_size);
+
+    let mut lines = break_lines_at(text, options, &measure);
+    let (width, height) = block_size(&lines, &measure);
+    let overflows = width > options.w || height > options.h;
+    if overflows && matches!(options.overflow, Overflow::Ellipsis) {
+        lines = truncate_lines(lines, options, &measure);
+    }
+
+    let lines = position_lines(text, lines, options, &measure);
+
+    TextLayout {
+        font_size,
+        lines,
+        width,
+        height,
+        overflows,
+    }
+}
+
+// Place one grapheme of the text in each cell of a comb of `cells` spread across the box, with
+// `spacing` points between cells, and centre it there. Text shorter than the comb starts in the
+// first cell, or ends in the last one when right aligned. With `Overflow::Shrink` the text is
+// shrunk until every grapheme fits its cell and the height of the box.
+pub fn layout_comb(text: &str, options: &LayoutOptions, cells: usize, spacing: f32) -> TextLayout {
+    let cell_width = (options.w - spacing * cells.saturating_sub(1) as f32) / cells as f32;
+    let graphemes: Vec<(usize, &str)> = text.grapheme_indices(true).collect();
+    let line = BrokenLine {
+        text: text.to_string(),
+        start: 0,
+        paragraph_end: true,
+        next: text.len(),
+    };
+
+    // Graphemes are painted on their own in their cells and measured the same way
+    let unshaped = Advances::unshaped();
+
+    // Widest grapheme and the height of the line at a size
+    let size_at = |font_size: f32| {
+        let measure = measure(options, &unshaped, font_size);
+        let widest = graphemes
+            .iter()
+            .map(|(start, grapheme)| measure.width(grapheme, *start))
+            .fold(0f32, f32::max);
+        (widest, block_height(&[measure.line_metrics(&line)]))
+    };
+
+    let (mut widest, mut height) = size_at(options.font_size);
+    let mut font_size = options.font_size;
+    if matches!(options.overflow, Overflow::Shrink) && (widest > cell_width || height > options.h) {
+        // Glyphs and lines grow in proportion to the font size
+        let scale = (cell_width / widest).min(options.h / height);
+        font_size = (font_size * scale).max(options.min_font_size.min(font_size));
+        (widest, height) = size_at(font_size);
+    }
+
+    let measure = measure(options, &unshaped, font_size);
+    let metrics = measure.line_metrics(&line);
+    let top = match options.align_v {
+        VerticalAlign::Top => 0.0,
+        VerticalAlign::Middle => (options.h - height) / 2.0,
+        VerticalAlign::Bottom => options.h - height,
+    };
+    let unused = cells.saturating_sub(graphemes.len());
+    let rtl = is_rtl(text);
+    let first_cell = match options.text_alignment {
+        TextAlignment::Right => unused,
+        TextAlignment::Center => unused / 2,
+        _ => 0,
+    };
+
+    let lines = graphemes
+        .into_iter()
+        .enumerate()
+        .filter(|(_, (_, grapheme))| !grapheme.trim().is_empty())
+        .map(|(index, (start, grapheme))| {
+            let width = measure.width(grapheme, start);
+            let cell_x = (first_cell + index) as f32 * (cell_width + spacing);
+
+            LayoutLine {
+                text: grapheme.to_string(),
+                segments: measure.segments(start, grapheme.len()),
+                x: cell_x + (cell_width - width) / 2.0,
+                baseline: top + metrics.ascent,
+                ascent: metrics.ascent,
+                descent: metrics.descent,
+                width,
+                word_spacing: options.spacing.word,
+                rtl,
+            }
+        })
+        .collect();
+
+    let width = options.w + (widest - cell_width).max(0.0) * cells as f32;
+    TextLayout {
+        font_size,
+        lines,
+        width,
+        height,
+        overflows: width > options.w || height > options.h,
+    }
+}
+
+// Lay out the start of the text at the requested size: as many lines as fit the box, and at
+// least one. Returns the layout and where the text left over starts.
+pub fn layout_text_part(text: &str, options: &LayoutOptions) -> (TextLayout, usize) {
+    let advances = Advances::new(text, options);
+    let measure = measure(options, &advances, options.font_size);
+
+    let mut lines = break_lines_at(text, options, &measure);
+    let line_count = fitting_line_count(&line_metrics(&lines, &measure), options.h);
+    let overflows = lines.len() > line_count;
+    lines.truncate(line_count);
+    let rest = lines.last().map_or(text.len(), |line| line.next);
+
+    let (width, height) = block_size(&lines, &measure);
+    let layout = TextLayout {
+        font_size: options.font_size,
+        lines: position_lines(text, lines, options, &measure),
+        width,
+        height,
+        overflows,
+    };
+
+    (layout, rest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fonts::{FontType, FontWeight, get_font};
+
+    fn options(w: f32, h: f32) -> LayoutOptions {
+        LayoutOptions {
+            font: get_font(&FontType::SansSerif, &FontWeight::Regular, false),
+            styles: vec![],
+            font_size: 10.0,
+            min_font_size: MIN_FONT_SIZE,
+            overflow: Overflow::Visible,
+            wrap: true,
+            hyphenation: None,
+            text_alignment: TextAlignment::Left,
+            align_v: VerticalAlign::Top,
+            line_height: None,
+            spacing: Spacing::default(),
+            tab_stops: vec![],
+            features: vec![],
+            w,
+            h,
+        }
+    }
+
+    fn texts(lines: &[BrokenLine]) -> Vec<&str> {
+        lines.iter().map(|line| line.text.as_str()).collect()
+    }
+
+    #[test]
+    fn break_lines_wraps_between_words() {
+        let options = options(0.0, 0.0);
+        let text = "the quick brown fox jumps over the lazy dog";
+        let advances = Advances::new(text, &options);
+        let measure = measure(&options, &advances, options.font_size);
+        let max_width = measure.width("the quick brown", 0) + 0.1;
+
+        let lines = break_lines(&measure, text, Some(max_width), None);
+
+        assert_eq!(texts(&lines)[0], "the quick brown");
+        assert!(
+            lines
+                .iter()
+                .all(|line| measure.width(&line.text, line.start) <= max_width)
+        );
+        assert_eq!(
+            texts(&lines).join(" "),
+            "the quick brown fox jumps over the lazy dog"
+        );
+        assert!(lines.last().unwrap().paragraph_end);
+    }
+
+    #[test]
+    fn break_lines_ends_lines_at_newlines() {
+        let options = options(0.0, 0.0);
+        let text = "one\ntwo three\r\nfour";
+        let advances = Advances::new(text, &options);
+        let measure = measure(&options, &advances, options.font_size);
+
+        let lines = break_lines(&measure, text, None, None);
+
+        assert_eq!(texts(&lines), ["one", "two three", "four"]);
+        assert!(lines.iter().all(|line| line.paragraph_end));
+        assert_eq!(lines[1].start, 4);
+        assert_eq!(lines[2].start, 15);
+    }
+
+    #[test]
+    fn break_lines_splits_words_wider_than_the_box() {
+        let options = options(0.0, 0.0);
+        let advances = Advances::new("abcdefghij", &options);
+        let measure = measure(&options, &advances, options.font_size);
+        let max_width = measure.width("abc", 0) + 0.1;
+
+        let lines = break_lines(&measure, "abcdefghij", Some(max_width), None);
+
+        assert!(lines.len() > 1);
+        assert_eq!(texts(&lines).concat(), "abcdefghij");
+        assert!(
+            lines
+                .iter()
+                .all(|line| measure.width(&line.text, line.start) <= max_width)
+        );
+    }
+
+    #[test]
+    fn break_lines_hyphenates_the_overflowing_word() {
+        let options = options(0.0, 0.0);
+        let advances = Advances::new("hyphenation", &options);
+        let measure = measure(&options, &advances, options.font_size);
+        let max_width = measure.width("hyphena", 0);
+
+        let lines = break_lines(
+            &measure,
+            "hyphenation",
+            Some(max_width),
+            Some(Lang::English),
+        );
+
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].text.ends_with('-'));
+        assert!(measure.width(&lines[0].text, 0) <= max_width);
+        assert_eq!(
+            format!("{}{}", lines[0].text.trim_end_matches('-'), lines[1].text),
+            "hyphenation"
+        );
+        assert_eq!(lines[1].start, lines[0].next);
+    }
+
+    #[test]
+    fn hyphenation_languages_are_read_from_their_code() {
+        assert_eq!(hyphenation_lang("notes", "EN").unwrap(), Lang::English);
+        assert_eq!(hyphenation_lang("notes", "xx").unwrap_err().field, "notes");
+        assert!(hyphenation_lang("notes", "eng").is_err());
+    }
+
+    #[test]
+    fn justified_lines_span_the_box_except_the_last() {
+        let mut options = options(120.0, 200.0);
+        options.text_alignment = TextAlignment::Justify;
+
+        let layout = layout_text(
+            "the quick brown fox jumps over the lazy dog and runs far away",
+            &options,
+        );
+
+        let (last, rest) = layout.lines.split_last().unwrap();
+        assert!(!rest.is_empty());
+        for line in rest {
+            assert_eq!(line.x, 0.0);
+            assert!((line.width - options.w).abs() < 0.01);
+            assert!(line.word_spacing > 0.0);
+        }
+        assert_eq!(last.word_spacing, 0.0);
+        assert!(last.width < options.w);
+    }
+
+    #[test]
+    fn justify_keeps_right_to_left_last_lines_on_the_right() {
+        let (x, word_spacing) = align_line(
+            &TextAlignment::Justify,
+            "שלום עולם",
+            50.0,
+            200.0,
+            true,
+            true,
+        );
+
+        assert_eq!((x, word_spacing), (150.0, 0.0));
+        assert_eq!(
+            align_line(&TextAlignment::Justify, "a b c", 50.0, 200.0, false, false),
+            (0.0, 75.0)
+        );
+    }
+
+    #[test]
+    fn wrapped_lines_keep_the_direction_of_their_paragraph() {
+        let hebrew = "\u{5E9}\u{5DC}\u{5D5}\u{5DD} \u{5E2}\u{5D5}\u{5DC}\u{5DD}";
+        let text = format!("{hebrew} abc \u{5E2}\u{5D5}\u{5DC}\u{5DD}");
+        let mut options = options(0.0, 100.0);
+        let advances = Advances::new(&text, &options);
+        options.w = measure(&options, &advances, options.font_size).width(hebrew, 0) + 1.0;
+        options.text_alignment = TextAlignment::Justify;
+
+        let layout = layout_text(&text, &options);
+
+        assert_eq!(layout.lines.len(), 2);
+        assert!(layout.lines[1].text.starts_with("abc"));
+        assert!(layout.lines.iter().all(|line| line.rtl));
+        // The last line of a justified right to left paragraph sits on the right
+        let last = &layout.lines[1];
+        assert!((last.x + last.width - options.w).abs() < 0.01);
+    }
+
+    #[test]
+    fn fit_font_size_keeps_text_that_fits() {
+        let options = options(500.0, 100.0);
+
+        let advances = Advances::new("short", &options);
+
+        assert_eq!(fit_font_size(&advances, &options), options.font_size);
+    }
+
+    #[test]
+    fn fit_font_size_shrinks_until_the_text_fits() {
+        let text = "a sentence that is far too long for the small box it is drawn in";
+        let options = options(60.0, 20.0);
+        let advances = Advances::new(text, &options);
+
+        let size = fit_font_size(&advances, &options);
+
+        assert!(size < options.font_size);
+        assert!(size >= options.min_font_size);
+        assert!(fits(&advances, &options, size));
+        assert!(!fits(&advances, &options, options.font_size));
+    }
+
+    #[test]
+    fn fit_font_size_stops_at_the_floor() {
+        let mut options = options(5.0, 5.0);
+        options.min_font_size = 4.0;
+        let advances = Advances::new("text that cannot fit at any allowed size", &options);
+
+        let size = fit_font_size(&advances, &options);
+
+        assert!((size - 4.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn parts_of_the_source_measure_as_if_shaped_alone() {
+        let mut options = options(0.0, 0.0);
+        options.spacing = Spacing {
+            letter: 0.5,
+            word: 2.0,
+        };
+        let text = "Wavy tops of hyphenation";
+        let parts = [
+            ("Wavy tops", 0),
+            ("tops of ", 5),
+            ("hyphen-", 13),
+            (text, 0),
+        ];
+        let advances = Advances::new(text, &options);
+        let unshaped = Advances::unshaped();
+
+        for size in [7.0, 10.0, 13.5] {
+            let source = measure(&options, &advances, size);
+            let alone = measure(&options, &unshaped, size);
+            for (part, start) in parts {
+                let difference = source.width(part, start) - alone.width(part, start);
+                assert!(difference.abs() < 0.01, "{part} at {size}");
+            }
+        }
+    }
+
+    #[test]
+    fn unwrapped_text_shrinks_until_its_longest_line_fits() {
+        let mut options = options(60.0, 100.0);
+        options.wrap = false;
+        options.overflow = Overflow::Shrink;
+
+        let layout = layout_text("a line far too long for the box\nshort", &options);
+
+        assert_eq!(layout.lines.len(), 2);
+        assert!(layout.font_size < options.font_size);
+        assert!(layout.width <= options.w);
+        assert!(!layout.overflows);
+    }
+
+    #[test]
+    fn truncate_lines_ends_the_last_line_with_an_ellipsis() {
+        let mut options = options(80.0, 30.0);
+        options.overflow = Overflow::Ellipsis;
+        let text = "one two three four five six seven eight nine ten eleven twelve";
+
+        let layout = layout_text(text, &options);
+
+        assert!(layout.overflows);
+        let last = layout.lines.last().unwrap();
+        assert!(last.text.ends_with(ELLIPSIS));
+        let advances = Advances::new(text, &options);
+        let measure = measure(&options, &advances, layout.font_size);
+        assert!(layout.lines.iter().all(|line| line.width <= options.w));
+        let lines = break_lines_at(text, &options, &measure);
+        assert!(layout.lines.len() < lines.len());
+        assert!(layout.lines.last().unwrap().baseline <= options.h);
+    }
+
+    #[test]
+    fn truncate_lines_shortens_lines_wider_than_the_box() {
+        let mut options = options(40.0, 100.0);
+        options.wrap = false;
+        let text = "a line much wider than its box";
+        let advances = Advances::new(text, &options);
+        let measure = measure(&options, &advances, options.font_size);
+        let lines = break_lines_at(text, &options, &measure);
+
+        let lines = truncate_lines(lines, &options, &measure);
+
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].text.ends_with(ELLIPSIS));
+        assert!(measure.width(&lines[0].text, 0) <= options.w);
+    }
+
+    // Left edge and right edge of each part of a tabbed line
+    fn cell_edges(layout: &TextLayout) -> Vec<(f32, f32)> {
+        layout
+            .lines
+            .iter()
+            .map(|line| (line.x, line.x + line.width))
+            .collect()
+    }
+
+    #[test]
+    fn tabs_advance_to_the_default_interval() {
+        let layout = layout_text("a\tb", &options(200.0, 100.0));
+
+        assert_eq!(layout.lines.len(), 2);
+        assert_eq!(layout.lines[1].x, TAB_INTERVAL);
+        assert_eq!(layout.lines[0].baseline, layout.lines[1].baseline);
+    }
+
+    #[test]
+    fn tab_stops_align_the_text_after_them() {
+        let mut options = options(200.0, 100.0);
+        options.tab_stops = vec![
+            TabStop {
+                position: 60.0,
+                align: Some(TabAlignment::Right),
+            },
+            TabStop {
+                position: 100.0,
+                align: Some(TabAlignment::Center),
+            },
+            TabStop {
+                position: 150.0,
+                align: Some(TabAlignment::Decimal),
+            },
+        ];
+
+        let layout = layout_text("Item\t12\tx\t3.50", &options);
+        let edges = cell_edges(&layout);
+        let unshaped = Advances::unshaped();
+        let measure = measure(&options, &unshaped, options.font_size);
+
+        assert_eq!(edges.len(), 4);
+        assert!((edges[1].1 - 60.0).abs() < 0.01);
+        assert!(((edges[2].0 + edges[2].1) / 2.0 - 100.0).abs() < 0.01);
+        assert!((edges[3].0 + measure.width("3", 0) - 150.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn tab_stops_ignore_the_alignment_of_the_text() {
+        let mut options = options(200.0, 100.0);
+        options.text_alignment = TextAlignment::Right;
+        options.tab_stops = vec![TabStop {
+            position: 100.0,
+            align: Some(TabAlignment::Decimal),
+        }];
+        let unshaped = Advances::unshaped();
+        let measure = measure(&options, &unshaped, options.font_size);
+
+        for text in ["Total\t12.50", "Tax\t3.5"] {
+            let layout = layout_text(text, &options);
+            let amount = layout.lines.last().unwrap();
+            let units = &amount.text[..amount.text.find('.').unwrap()];
+            assert!((amount.x + measure.width(units, 0) - 100.0).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn comb_centres_one_grapheme_per_cell() {
+        let options = options(100.0, 20.0);
+
+        let layout = layout_comb("abc", &options, 5, 0.0);
+
+        assert_eq!(layout.lines.len(), 3);
+        for (index, line) in layout.lines.iter().enumerate() {
+            let centre = line.x + line.width / 2.0;
+            assert!((centre - (index as f32 * 20.0 + 10.0)).abs() < 0.01);
+        }
+        assert!(!layout.overflows);
+    }
+
+    #[test]
+    fn comb_right_alignment_fills_the_last_cells() {
+        let mut options = options(100.0, 20.0);
+        options.text_alignment = TextAlignment::Right;
+
+        let layout = layout_comb("12", &options, 5, 5.0);
+
+        let cell_width = (100.0 - 5.0 * 4.0) / 5.0;
+        let last = layout.lines.last().unwrap();
+        assert!(
+            (last.x + last.width / 2.0 - (4.0 * (cell_width + 5.0) + cell_width / 2.0)).abs()
+                < 0.01
+        );
+        assert_eq!(layout.lines[0].text, "1");
+    }
+
+    #[test]
+    fn comb_shrinks_graphemes_wider_than_their_cells() {
+        let mut options = options(20.0, 20.0);
+        options.overflow = Overflow::Shrink;
+        options.font_size = 40.0;
+
+        let layout = layout_comb("WM", &options, 4, 0.0);
+
+        assert!(layout.font_size < 40.0);
+        assert!(layout.lines.iter().all(|line| line.width <= 5.0 + 0.01));
+        assert!(!layout.overflows);
+    }
+}
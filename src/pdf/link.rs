@@ -1,6 +1,6 @@
 use lopdf::{Dictionary, Document, Object};
 
-use crate::{error::BoxedError, pdf::get_page_media_box};
+use crate::{error::BoxedError, pdf::get_box_frame};
 
 #[derive(Debug)]
 pub struct DrawLinkOptions<'a> {
@@ -16,14 +16,15 @@ pub fn add_link<'a>(
     page: &(u32, u16),
     options: DrawLinkOptions<'a>,
 ) -> Result<(), BoxedError> {
-    let (_, _, _page_w, page_h) = get_page_media_box(document, page)?;
+    let frame = get_box_frame(
+        document,
+        page,
+        (options.x, options.y, options.w, options.h),
+        0.0,
+    )?;
 
-    let rect = vec![
-        Object::Real(options.x),                      // x1
-        Object::Real(page_h - options.y - options.h), // y1
-        Object::Real(options.x + options.w),          // x2
-        Object::Real(page_h - options.y),             // y2
-    ];
+    // x1, y1, x2, y2
+    let rect = frame.bounds().into_iter().map(Object::Real).collect();
 
     let annotation = Dictionary::from_iter(vec![
         ("Type", Object::Name("Annot".into())),
@@ -0,0 +1,126 @@
+use lopdf::{
+    Document, Object,
+    content::{Content, Operation},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::error::BoxedError;
+
+use super::{color::Color, get_box_frame};
+
+// Bezier control distance approximating a quarter circle of radius 1
+const CIRCLE_CONTROL: f32 = 0.552_284_8;
+// Stroke width of check marks and crosses, as a fraction of the mark size
+const STROKE_WIDTH: f32 = 0.12;
+
+// Shape drawn in a checked box or selected radio button
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MarkStyle {
+    Check,
+    Cross,
+    // Filled circle
+    Circle,
+    // Filled square
+    Square,
+}
+
+pub struct DrawMarkOptions {
+    pub style: MarkStyle,
+    pub color: Option<Color>,
+    pub x: f32,
+    pub y: f32,
+    pub w: f32,
+    pub h: f32,
+    // Degrees counterclockwise, the mark is drawn in the turned box
+    pub rotation: f32,
+}
+
+fn point(operator: &str, x: f32, y: f32) -> Operation {
+    Operation::new(operator, vec![x.into(), y.into()])
+}
+
+// Path of the mark in a unit square, y pointing up
+fn mark_path(style: MarkStyle) -> Vec<Operation> {
+    match style {
+        MarkStyle::Check => vec![
+            point("m", 0.15, 0.5),
+            point("l", 0.4, 0.22),
+            point("l", 0.85, 0.8),
+            Operation::new("S", vec![]),
+        ],
+        MarkStyle::Cross => vec![
+            point("m", 0.2, 0.2),
+            point("l", 0.8, 0.8),
+            point("m", 0.2, 0.8),
+            point("l", 0.8, 0.2),
+            Operation::new("S", vec![]),
+        ],
+        MarkStyle::Circle => {
+            let (c, r) = (0.5, 0.3);
+            let k = r * CIRCLE_CONTROL;
+            let curve = |points: [f32; 6]| {
+                Operation::new("c", points.into_iter().map(Object::Real).collect())
+            };
+            vec![
+                point("m", c + r, c),
+                curve([c + r, c + k, c + k, c + r, c, c + r]),
+                curve([c - k, c + r, c - r, c + k, c - r, c]),
+                curve([c - r, c - k, c - k, c - r, c, c - r]),
+                curve([c + k, c - r, c + r, c - k, c + r, c]),
+                Operation::new("f", vec![]),
+            ]
+        }
+        MarkStyle::Square => vec![
+            Operation::new("re", vec![0.25.into(), 0.25.into(), 0.5.into(), 0.5.into()]),
+            Operation::new("f", vec![]),
+        ],
+    }
+}
+
+// Draw a mark centred in the box, as large as its shorter side
+pub fn draw_mark(
+    document: &mut Document,
+    page: &(u32, u16),
+    options: DrawMarkOptions,
+) -> Result<(), BoxedError> {
+    let frame = get_box_frame(
+        document,
+        page,
+        (options.x, options.y, options.w, options.h),
+        options.rotation,
+    )?;
+
+    let size = frame.w.min(frame.h);
+    let color = options.color.unwrap_or(Color::from_rgb(0, 0, 0));
+    let rgb = || vec![color.r().into(), color.g().into(), color.b().into()];
+
+    let mut content = Content {
+        operations: vec![
+            Operation::new("q", vec![]), // Save graphics state
+            frame.transform(),           // Into the box
+            Operation::new(
+                "cm",
+                vec![
+                    size.into(),
+                    0.into(),
+                    0.into(),
+                    size.into(),
+                    ((frame.w - size) / 2.0).into(),
+                    ((frame.h - size) / 2.0).into(),
+                ],
+            ), // Into the unit square of the mark
+            Operation::new("rg", rgb()), // Fill color
+            Operation::new("RG", rgb()), // Stroke color
+            Operation::new("w", vec![STROKE_WIDTH.into()]), // Line width
+            Operation::new("J", vec![1.into()]), // Round caps
+            Operation::new("j", vec![1.into()]), // Round joins
+        ],
+    };
+    content.operations.extend(mark_path(options.style));
+    content.operations.push(Operation::new("Q", vec![])); // Restore graphics state
+
+    document.add_page_contents(page.to_owned(), content.encode()?)?;
+
+    Ok(())
+}
@@ -0,0 +1,356 @@
+use pulldown_cmark::{Event, HeadingLevel, Parser, Tag, TagEnd};
+
+use crate::{
+    fonts::{FontWeight, text_width},
+    pdf::{
+        Overflow, VerticalAlign,
+        layout::{LayoutLine, MIN_FONT_SIZE, RunStyle, TextLayout, layout_text},
+        markup::{RichText, SpanStyle},
+        text::DrawTextOptions,
+    },
+};
+
+// Indent of each level of list nesting, in ems
+const LIST_INDENT: f32 = 1.5;
+// Space between a list marker and the item text, in ems
+const MARKER_GAP: f32 = 0.4;
+// Space after a block, in ems. Items of the same list sit closer together.
+const BLOCK_SPACING: f32 = 0.6;
+const LIST_ITEM_SPACING: f32 = 0.25;
+
+// A paragraph, heading or list item of a Markdown document
+#[derive(Debug, Clone)]
+pub struct MarkdownBlock {
+    pub rich_text: RichText,
+    pub heading: Option<HeadingLevel>,
+    // Lists the block is nested in
+    pub depth: usize,
+    // Bullet or number in front of the first line of a list item
+    pub marker: Option<String>,
+}
+
+impl MarkdownBlock {
+    // Font size of the block as a multiple of the document's
+    fn scale(&self) -> f32 {
+        match self.heading {
+            Some(HeadingLevel::H1) => 1.6,
+            Some(HeadingLevel::H2) => 1.35,
+            Some(HeadingLevel::H3) => 1.15,
+            _ => 1.0,
+        }
+    }
+
+    fn in_list(&self) -> bool {
+        self.depth > 0
+    }
+}
+
+#[derive(Default)]
+struct BlockBuilder {
+    blocks: Vec<MarkdownBlock>,
+    current: Option<MarkdownBlock>,
+    // Inline styles open at the current position, innermost last
+    styles: Vec<SpanStyle>,
+    // Next number of each open list, none for bullet lists
+    lists: Vec<Option<u64>>,
+    marker: Option<String>,
+}
+
+impl BlockBuilder {
+    fn begin_block(&mut self, heading: Option<HeadingLevel>) {
+        self.finish_block();
+        self.current = Some(MarkdownBlock {
+            rich_text: RichText::default(),
+            heading,
+            depth: self.lists.len(),
+            marker: self.marker.take(),
+        });
+    }
+
+    fn finish_block(&mut self) {
+        if let Some(block) = self.current.take()
+            && !block.rich_text.text.trim().is_empty()
+        {
+            self.blocks.push(block);
+        }
+    }
+
+    fn push_text(&mut self, text: &str) {
+        if self.current.is_none() {
+            self.begin_block(None);
+        }
+
+        let style = self
+            .styles
+            .iter()
+            .fold(SpanStyle::default(), |outer, style| style.within(outer));
+        if let Some(block) = &mut self.current {
+            block.rich_text.push(text, style);
+        }
+    }
+
+    fn begin_item(&mut self) {
+        self.finish_block();
+        let depth = self.lists.len();
+        self.marker = match self.lists.last_mut() {
+            Some(Some(number)) => {
+                *number += 1;
+                Some(format!("{}.", *number - 1))
+            }
+            _ => Some(
+                match depth {
+                    0 | 1 => "\u{2022}",
+                    2 => "\u{25E6}",
+                    _ => "\u{25AA}",
+                }
+                .to_string(),
+            ),
+        };
+    }
+}
+
+// Split a Markdown document into the blocks laid out one below the other: paragraphs, headings,
+// list items (with their markers) and code blocks. Emphasis and strong emphasis become italic and
+// bold spans. Other constructs keep their text and lose their formatting.
+pub fn parse_markdown(source: &str) -> Vec<MarkdownBlock> {
+    let mut builder = BlockBuilder::default();
+
+    for event in Parser::new(source) {
+        match event {
+            Event::Start(Tag::Paragraph | Tag::CodeBlock(_)) => builder.begin_block(None),
+            Event::Start(Tag::Heading { level, .. }) => builder.begin_block(Some(level)),
+            Event::End(TagEnd::Paragraph | TagEnd::Heading(_) | TagEnd::CodeBlock) => {
+                builder.finish_block()
+            }
+            Event::Start(Tag::List(start)) => {
+                builder.finish_block();
+                builder.lists.push(start);
+            }
+            Event::End(TagEnd::List(_)) => {
+                builder.finish_block();
+                builder.lists.pop();
+            }
+            Event::Start(Tag::Item) => builder.begin_item(),
+            Event::End(TagEnd::Item) => builder.finish_block(),
+            Event::Start(Tag::Emphasis) => builder.styles.push(SpanStyle {
+                italic: Some(true),
+                ..Default::default()
+            }),
+            Event::Start(Tag::Strong) => builder.styles.push(SpanStyle {
+                font_weight: Some(FontWeight::Bold),
+                ..Default::default()
+            }),
+            Event::End(TagEnd::Emphasis | TagEnd::Strong) => {
+                builder.styles.pop();
+            }
+            Event::Text(text) | Event::Code(text) => builder.push_text(&text),
+            Event::SoftBreak => builder.push_text(" "),
+            Event::HardBreak => builder.push_text("\n"),
+            _ => {}
+        }
+    }
+    builder.finish_block();
+
+    builder.blocks
+}
+
+// Lay out the blocks one below the other in the box of `options`, with the document at
+// `font_size`. Headings are set larger and bold, list items are indented by their depth with
+// their marker hanging in front of the first line.
+fn layout_blocks(
+    blocks: &[MarkdownBlock],
+    options: &DrawTextOptions,
+    font_size: f32,
+) -> TextLayout {
+    let mut lines: Vec<LayoutLine> = vec![];
+    let mut width: f32 = 0.0;
+    let mut top = 0.0;
+    let mut previous: Option<&MarkdownBlock> = None;
+
+    for block in blocks {
+        if let Some(previous) = previous {
+            let spacing = if previous.in_list() && block.in_list() {
+                LIST_ITEM_SPACING
+            } else {
+                BLOCK_SPACING
+            };
+            top += spacing * font_size;
+        }
+        previous = Some(block);
+
+        let indent = block.depth as f32 * LIST_INDENT * font_size;
+        let block_font_size = font_size * block.scale();
+        let block_options = DrawTextOptions {
+            text: &block.rich_text.text,
+            styles: &block.rich_text.spans,
+            font_size: block_font_size,
+            font_weight: match block.heading {
+                Some(_) => Some(FontWeight::Bold),
+                None => options.font_weight,
+            },
+            w: options.frame_size().0 - indent,
+            ..options.clone()
+        };
+        // The overflow policy applies to the whole document, not to each block
+        let mut layout_options =
+            block_options.layout_options(block_font_size, true, Overflow::Visible);
+        layout_options.overflow = Overflow::Visible;
+        layout_options.align_v = VerticalAlign::Top;
+        let layout = layout_text(&block.rich_text.text, &layout_options);
+
+        // Styles are relative to the size of the whole document
+        let relative = block_font_size / font_size;
+        let block_lines = layout.lines.into_iter().map(|mut line| {
+            for (_, style) in &mut line.segments {
+                style.scale *= relative;
+            }
+            line.x += indent;
+            line.baseline += top;
+            line
+        });
+        let first_line = lines.len();
+        lines.extend(block_lines);
+
+        if let (Some(marker), Some(first)) = (&block.marker, lines.get(first_line)) {
+            let font = options.font();
+            let marker_width =
+                text_width(font, marker, font_size, options.spacing, options.features);
+            lines.push(LayoutLine {
+                text: marker.clone(),
+                segments: vec![(
+                    0,
+                    RunStyle {
+                        font,
+                        scale: 1.0,
+                        color: None,
+                    },
+                )],
+                x: indent - marker_width - MARKER_GAP * font_size,
+                baseline: first.baseline,
+                ascent: first.ascent,
+                descent: first.descent,
+                width: marker_width,
+                word_spacing: options.spacing.word,
+                rtl: first.rtl,
+            });
+        }
+
+        width = width.max(indent + layout.width);
+        top += layout.height;
+    }
+
+    let (box_w, box_h) = options.frame_size();
+    let offset = match options.align_v.clone().unwrap_or(VerticalAlign::Top) {
+        VerticalAlign::Top => 0.0,
+        VerticalAlign::Middle => (box_h - top) / 2.0,
+        VerticalAlign::Bottom => box_h - top,
+    };
+    for line in &mut lines {
+        line.baseline += offset;
+    }
+
+    TextLayout {
+        font_size,
+        lines,
+        width,
+        height: top,
+        overflows: width > box_w || top > box_h,
+    }
+}
+
+// Lay out a Markdown document in the box of `options`. Unless another overflow policy is set the
+// document is shrunk until it fits, down to the minimum font size.
+pub fn layout_markdown(blocks: &[MarkdownBlock], options: &DrawTextOptions) -> TextLayout {
+    let layout = layout_blocks(blocks, options, options.font_size);
+    if !layout.overflows
+        || !matches!(
+            options.overflow.unwrap_or(Overflow::Shrink),
+            Overflow::Shrink
+        )
+    {
+        return layout;
+    }
+
+    let mut min = options
+        .min_font_size
+        .unwrap_or(MIN_FONT_SIZE)
+        .min(options.font_size);
+    let mut max = options.font_size;
+    while max - min > 0.1 {
+        let size = (min + max) / 2.0;
+        if layout_blocks(blocks, options, size).overflows {
+            max = size;
+        } else {
+            min = size;
+        }
+    }
+
+    layout_blocks(blocks, options, min)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Marker, depth and text of each block
+    fn outline(source: &str) -> Vec<(Option<String>, usize, String)> {
+        parse_markdown(source)
+            .into_iter()
+            .map(|block| (block.marker, block.depth, block.rich_text.text))
+            .collect()
+    }
+
+    fn item(marker: &str, depth: usize, text: &str) -> (Option<String>, usize, String) {
+        (Some(marker.to_string()), depth, text.to_string())
+    }
+
+    #[test]
+    fn ordered_lists_number_their_items_from_the_start() {
+        assert_eq!(
+            outline("1. one\n2. two\n3. three"),
+            [
+                item("1.", 1, "one"),
+                item("2.", 1, "two"),
+                item("3.", 1, "three")
+            ]
+        );
+        assert_eq!(
+            outline("7. seven\n1. eight"),
+            [item("7.", 1, "seven"), item("8.", 1, "eight")]
+        );
+    }
+
+    #[test]
+    fn nested_lists_change_bullets_and_restart_numbers() {
+        let source = "- a\n  - b\n    - c\n      - d\n- e\n  1. f\n  2. g\n";
+
+        assert_eq!(
+            outline(source),
+            [
+                item("\u{2022}", 1, "a"),
+                item("\u{25E6}", 2, "b"),
+                item("\u{25AA}", 3, "c"),
+                item("\u{25AA}", 4, "d"),
+                item("\u{2022}", 1, "e"),
+                item("1.", 2, "f"),
+                item("2.", 2, "g"),
+            ]
+        );
+    }
+
+    #[test]
+    fn headings_and_paragraphs_have_no_marker() {
+        let blocks = parse_markdown("# Title\n\nSome *soft*\nwrapped **text**");
+
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].heading, Some(HeadingLevel::H1));
+        assert_eq!(blocks[1].rich_text.text, "Some soft wrapped text");
+        assert!(
+            blocks
+                .iter()
+                .all(|block| block.marker.is_none() && block.depth == 0)
+        );
+        let bold = blocks[1].rich_text.spans.last().unwrap();
+        assert!(matches!(bold.style.font_weight, Some(FontWeight::Bold)));
+    }
+}
@@ -0,0 +1,323 @@
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{error::GenericError, fonts::FontWeight, pdf::color::Color};
+
+// Markup a text variable value is written in
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Markup {
+    // Inline HTML: <b>, <strong>, <i>, <em>, <span color="#rrggbb" size="14">, and <br>
+    Html,
+}
+
+// Formatting of a span of text. Unset fields come from the enclosing span, or from the variable
+// outside of any span.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SpanStyle {
+    pub font_weight: Option<FontWeight>,
+    pub italic: Option<bool>,
+    pub color: Option<Color>,
+    // Font size in points
+    pub font_size: Option<f32>,
+}
+
+impl SpanStyle {
+    // This style nested in `outer`
+    pub fn within(self, outer: SpanStyle) -> SpanStyle {
+        SpanStyle {
+            font_weight: self.font_weight.or(outer.font_weight),
+            italic: self.italic.or(outer.italic),
+            color: self.color.or(outer.color),
+            font_size: self.font_size.or(outer.font_size),
+        }
+    }
+}
+
+// A styled range of the plain text, in bytes
+#[derive(Debug, Clone)]
+pub struct StyledSpan {
+    pub range: Range<usize>,
+    pub style: SpanStyle,
+}
+
+// Plain text and the formatting of its spans
+#[derive(Debug, Clone, Default)]
+pub struct RichText {
+    pub text: String,
+    pub spans: Vec<StyledSpan>,
+}
+
+impl RichText {
+    pub fn push(&mut self, text: &str, style: SpanStyle) {
+        if text.is_empty() {
+            return;
+        }
+
+        let start = self.text.len();
+        self.text.push_str(text);
+        self.spans.push(StyledSpan {
+            range: start..self.text.len(),
+            style,
+        });
+    }
+}
+
+// Spans of the text from `offset` on, with ranges relative to it
+pub fn skip_spans(spans: &[StyledSpan], offset: usize) -> Vec<StyledSpan> {
+    spans
+        .iter()
+        .filter(|span| span.range.end > offset)
+        .map(|span| StyledSpan {
+            range: span.range.start.saturating_sub(offset)..span.range.end - offset,
+            style: span.style,
+        })
+        .collect()
+}
+
+fn decode_entity(entity: &str) -> Option<char> {
+    match entity {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some('\u{A0}'),
+        _ => {
+            let code = entity.strip_prefix('#')?;
+            let code = match code.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => code.parse().ok()?,
+            };
+            char::from_u32(code)
+        }
+    }
+}
+
+// Replace character references in text between tags
+fn decode_text(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(index) = rest.find('&') {
+        decoded.push_str(&rest[..index]);
+        rest = &rest[index..];
+
+        let entity = rest[1..]
+            .find(';')
+            .and_then(|end| Some((decode_entity(&rest[1..end + 1])?, end + 2)));
+        match entity {
+            Some((ch, len)) => {
+                decoded.push(ch);
+                rest = &rest[len..];
+            }
+            // A lone ampersand is kept as it is
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+
+    decoded
+}
+
+// Attributes of a tag as name and value pairs. Values may be quoted with either quote or not at
+// all.
+fn parse_attributes(source: &str) -> Result<Vec<(String, String)>, GenericError> {
+    let mut attributes = vec![];
+    let mut rest = source.trim_start();
+
+    while !rest.is_empty() {
+        let name_end = rest
+            .find(|ch: char| ch == '=' || ch.is_whitespace())
+            .unwrap_or(rest.len());
+        let name = rest[..name_end].to_ascii_lowercase();
+        rest = rest[name_end..].trim_start();
+
+        let Some(value) = rest.strip_prefix('=') else {
+            return Err(GenericError(format!("Attribute {name} has no value")));
+        };
+        let value = value.trim_start();
+
+        let (value, after) = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let end = value[1..].find(quote).ok_or(GenericError(format!(
+                    "Unterminated value of attribute {name}"
+                )))?;
+                (&value[1..end + 1], &value[end + 2..])
+            }
+            _ => {
+                let end = value.find(char::is_whitespace).unwrap_or(value.len());
+                (&value[..end], &value[end..])
+            }
+        };
+
+        attributes.push((name, decode_text(value)));
+        rest = after.trim_start();
+    }
+
+    Ok(attributes)
+}
+
+// Style a `<span>` sets from its attributes
+fn span_style(attributes: &str) -> Result<SpanStyle, GenericError> {
+    let mut style = SpanStyle::default();
+
+    for (name, value) in parse_attributes(attributes)? {
+        match name.as_str() {
+            "color" => {
+                style.color =
+                    Some(value.parse().map_err(|err| {
+                        GenericError(format!("Invalid span color {value}: {err}"))
+                    })?);
+            }
+            "size" => {
+                let size: f32 = value
+                    .trim_end_matches("pt")
+                    .parse()
+                    .ok()
+                    .filter(|size: &f32| *size > 0.0)
+                    .ok_or(GenericError(format!("Invalid span size {value}")))?;
+                style.font_size = Some(size);
+            }
+            _ => return Err(GenericError(format!("Unsupported span attribute {name}"))),
+        }
+    }
+
+    Ok(style)
+}
+
+// Parse inline HTML into plain text and styled spans. Only the tags of `Markup::Html` are
+// accepted, anything else is an error so typos do not end up printed on the document.
+pub fn parse_html(markup: &str) -> Result<RichText, GenericError> {
+    let mut rich_text = RichText::default();
+    // Open tags and the style inside each of them
+    let mut stack: Vec<(String, SpanStyle)> = vec![];
+    let mut rest = markup;
+
+    loop {
+        let style = stack.last().map(|(_, style)| *style).unwrap_or_default();
+        let Some(tag_start) = rest.find('<') else {
+            rich_text.push(&decode_text(rest), style);
+            break;
+        };
+        rich_text.push(&decode_text(&rest[..tag_start]), style);
+
+        let tag_end = rest[tag_start..]
+            .find('>')
+            .map(|end| tag_start + end)
+            .ok_or(GenericError("Unterminated tag in markup".into()))?;
+        let tag = rest[tag_start + 1..tag_end].trim();
+        rest = &rest[tag_end + 1..];
+
+        if let Some(name) = tag.strip_prefix('/') {
+            let name = name.trim().to_ascii_lowercase();
+            match stack.pop() {
+                Some((open, _)) if open == name => {}
+                Some((open, _)) => {
+                    return Err(GenericError(format!(
+                        "Closing tag </{name}> does not match <{open}>"
+                    )));
+                }
+                None => {
+                    return Err(GenericError(format!(
+                        "Closing tag </{name}> was never opened"
+                    )));
+                }
+            }
+            continue;
+        }
+
+        let (tag, self_closing) = match tag.strip_suffix('/') {
+            Some(tag) => (tag.trim_end(), true),
+            None => (tag, false),
+        };
+        let name_end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+        let (name, attributes) = tag.split_at(name_end);
+        let name = name.to_ascii_lowercase();
+
+        let tag_style = match name.as_str() {
+            "br" => {
+                rich_text.push("\n", style);
+                continue;
+            }
+            "b" | "strong" => SpanStyle {
+                font_weight: Some(FontWeight::Bold),
+                ..Default::default()
+            },
+            "i" | "em" => SpanStyle {
+                italic: Some(true),
+                ..Default::default()
+            },
+            "span" => span_style(attributes)?,
+            _ => return Err(GenericError(format!("Unsupported markup tag <{name}>"))),
+        };
+        if name != "span" && !attributes.trim().is_empty() {
+            return Err(GenericError(format!("Tag <{name}> takes no attributes")));
+        }
+
+        if !self_closing {
+            stack.push((name, tag_style.within(style)));
+        }
+    }
+
+    match stack.last() {
+        Some((open, _)) => Err(GenericError(format!("Tag <{open}> is never closed"))),
+        None => Ok(rich_text),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(markup: &str) -> String {
+        parse_html(markup).unwrap_err().0
+    }
+
+    #[test]
+    fn parse_html_styles_nested_spans() {
+        let rich_text =
+            parse_html("a <b>b <span color=\"#ff0000\" size='14'>c</span></b><br/>&amp;&#65;")
+                .unwrap();
+
+        assert_eq!(rich_text.text, "a b c\n&A");
+        let c = rich_text
+            .spans
+            .iter()
+            .find(|span| span.range == (4..5))
+            .unwrap();
+        assert!(matches!(c.style.font_weight, Some(FontWeight::Bold)));
+        assert_eq!(c.style.color, Some(Color::from_rgb(0xFF, 0, 0)));
+        assert_eq!(c.style.font_size, Some(14.0));
+        let a = &rich_text.spans[0];
+        assert_eq!(a.range, 0..2);
+        assert!(a.style.font_weight.is_none());
+    }
+
+    #[test]
+    fn parse_html_rejects_broken_tags() {
+        assert_eq!(error("a <b"), "Unterminated tag in markup");
+        assert_eq!(
+            error("<b><i>a</b></i>"),
+            "Closing tag </b> does not match <i>"
+        );
+        assert_eq!(error("a</b>"), "Closing tag </b> was never opened");
+        assert_eq!(error("<b>a"), "Tag <b> is never closed");
+    }
+
+    #[test]
+    fn parse_html_rejects_unsupported_tags_and_attributes() {
+        assert_eq!(error("<u>a</u>"), "Unsupported markup tag <u>");
+        assert_eq!(error("<b class=x>a</b>"), "Tag <b> takes no attributes");
+        assert!(error("<span color=red>a</span>").starts_with("Invalid span color red"));
+        assert_eq!(error("<span size=-2>a</span>"), "Invalid span size -2");
+        assert_eq!(
+            error("<span weight=bold>a</span>"),
+            "Unsupported span attribute weight"
+        );
+    }
+}
@@ -1,17 +1,29 @@
 use std::{collections::HashMap, sync::Arc};
 
 use color::Color;
-use lopdf::Document;
-use serde::{Deserialize, Serialize};
+use lopdf::{Dictionary, Document, Object, content::Operation, dictionary};
+use mark::MarkStyle;
+use markup::{Markup, RichText, parse_html};
+use qr::QrErrorCorrection;
+use rustybuzz::Feature;
+use serde::{Deserialize, Deserializer, Serialize, de::Error as _};
 
-use crate::error::{AppError, BoxedError, GenericError};
+use crate::{
+    error::{BoxedError, GenericError, InvalidOptionError},
+    fonts::{FontType, FontWeight, parse_feature},
+};
 
 pub mod acroform;
 pub mod color;
 pub mod font;
 pub mod image;
+pub mod layout;
 pub mod link;
+pub mod mark;
+pub mod markdown;
+pub mod markup;
 pub mod pool;
+pub mod qr;
 pub mod text;
 
 pub type UserVariables = HashMap<String, UserVariableValue>;
@@ -29,7 +41,37 @@ pub struct VariableOptions {
     pub h: f32,
     pub page: usize,
     pub field: String,
+    #[serde(deserialize_with = "text_or_bool")]
     pub value: String,
+    // Degrees the content is turned counterclockwise around the centre of the box
+    pub rotation: Option<f32>,
+}
+
+// A value given as text, or as a JSON boolean for checkboxes and radio buttons, which is read as
+// `true` or `false`
+fn text_or_bool<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum TextOrBool {
+        Text(String),
+        Bool(bool),
+    }
+
+    Ok(match TextOrBool::deserialize(deserializer)? {
+        TextOrBool::Text(text) => text,
+        TextOrBool::Bool(value) => value.to_string(),
+    })
+}
+
+// An opacity, rejected unless it is between 0 and 1
+fn opacity<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f32>, D::Error> {
+    let opacity = Option::<f32>::deserialize(deserializer)?;
+    match opacity {
+        Some(value) if !(0.0..=1.0).contains(&value) => Err(D::Error::custom(format!(
+            "opacity {value} is not between 0 and 1"
+        ))),
+        _ => Ok(opacity),
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -40,6 +82,52 @@ pub enum TextAlignment {
     Center,
     #[serde(rename = "right")]
     Right,
+    #[serde(rename = "justify")]
+    Justify,
+}
+
+// What happens to text larger than its box
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Overflow {
+    // Draw the text past the edges of the box
+    Visible,
+    // Shrink the text until it fits, down to a minimum font size
+    Shrink,
+    // Cut the text off at the edges of the box
+    Clip,
+    // Drop what does not fit and end the text with an ellipsis
+    Ellipsis,
+    // Refuse to draw the text
+    Fail,
+}
+
+// How glyphs are painted, the text rendering modes of `Tr`
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RenderMode {
+    #[default]
+    Fill,
+    // Outlines in the text color
+    Stroke,
+    FillStroke,
+    // Neither filled nor stroked, but still selectable and searchable
+    Invisible,
+}
+
+impl RenderMode {
+    pub fn operand(self) -> i64 {
+        match self {
+            RenderMode::Fill => 0,
+            RenderMode::Stroke => 1,
+            RenderMode::FillStroke => 2,
+            RenderMode::Invisible => 3,
+        }
+    }
+
+    pub fn strokes(self) -> bool {
+        matches!(self, RenderMode::Stroke | RenderMode::FillStroke)
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -50,6 +138,69 @@ pub enum VerticalAlign {
     Bottom,
 }
 
+// Outline drawn around the box of a variable
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Border {
+    // Line width in points, 1 if unset
+    pub width: Option<f32>,
+    // Text color if unset
+    pub color: Option<Color>,
+}
+
+// How the text after a tab lines up with its tab stop
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TabAlignment {
+    // Text starts at the stop
+    #[default]
+    Left,
+    // Text ends at the stop
+    Right,
+    Center,
+    // The decimal point of the text sits at the stop, text without one ends at it
+    Decimal,
+}
+
+// Position a tab advances the text after it to
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct TabStop {
+    // Points from the left edge of the box. Lines with tabs start at that edge whatever the
+    // alignment of the text.
+    pub position: f32,
+    // Left if unset
+    pub align: Option<TabAlignment>,
+}
+
+// Row of printed boxes holding one character each, spread evenly across the width of the
+// variable
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Comb {
+    pub cells: usize,
+    // Gap between cells in points, none if unset
+    pub spacing: Option<f32>,
+}
+
+// A box text continues in once the boxes before it are full
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContinuationBox {
+    pub page: usize,
+    pub x: f32,
+    pub y: f32,
+    pub w: f32,
+    pub h: f32,
+}
+
+// Page appended to the document, as a copy of `template_page`, for text left over after the last
+// continuation box. Text continues in the box of each copy.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OverflowPage {
+    pub template_page: usize,
+    pub x: f32,
+    pub y: f32,
+    pub w: f32,
+    pub h: f32,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TextVariable {
     #[serde(flatten)]
@@ -59,6 +210,157 @@ pub struct TextVariable {
     pub align_v: Option<VerticalAlign>,
     pub color: Option<Color>,
     pub wrap: Option<bool>,
+    pub font_type: Option<FontType>,
+    pub font_weight: Option<FontWeight>,
+    pub italic: Option<bool>,
+    // ISO 639-1 code of the language wrapped lines are hyphenated in, no hyphenation if unset
+    pub hyphenation: Option<String>,
+    // Distance between baselines as a multiple of the font size
+    pub line_height: Option<f32>,
+    // Extra space in points after every character and after every space
+    pub letter_spacing: Option<f32>,
+    pub word_spacing: Option<f32>,
+    pub overflow: Option<Overflow>,
+    // Smallest size text is shrunk to with the `shrink` overflow policy
+    pub min_font_size: Option<f32>,
+    pub continuations: Option<Vec<ContinuationBox>>,
+    pub overflow_page: Option<OverflowPage>,
+    pub underline: Option<bool>,
+    pub strikethrough: Option<bool>,
+    // Highlight behind each line of text
+    pub background_color: Option<Color>,
+    pub border: Option<Border>,
+    // Markup the value is written in, plain text if unset
+    pub markup: Option<Markup>,
+    // Stops tab characters advance to. Past the last one tabs advance to every half inch.
+    pub tab_stops: Option<Vec<TabStop>>,
+    pub comb: Option<Comb>,
+    // OpenType features, as `smcp`, `tnum`, `onum`, `dlig`, `ss01` or `-liga` to turn one off
+    pub font_features: Option<Vec<String>>,
+    pub render_mode: Option<RenderMode>,
+    // Opacity of the text and its decorations, from 0 (transparent) to 1
+    #[serde(default, deserialize_with = "opacity")]
+    pub opacity: Option<f32>,
+}
+
+impl TextVariable {
+    // Value of the variable as plain text and the styles of its spans
+    pub fn rich_text(&self) -> Result<RichText, InvalidOptionError> {
+        match self.markup {
+            Some(Markup::Html) => {
+                parse_html(&self.variable.value).map_err(|err| InvalidOptionError {
+                    field: self.variable.field.clone(),
+                    message: err.0,
+                })
+            }
+            None => Ok(RichText {
+                text: self.variable.value.clone(),
+                spans: vec![],
+            }),
+        }
+    }
+
+    // OpenType features the value is shaped with
+    pub fn features(&self) -> Result<Vec<Feature>, InvalidOptionError> {
+        parse_features(&self.variable.field, &self.font_features)
+    }
+
+    // Signatures are drawn in their own box only, the text cannot continue in other boxes
+    pub fn check_signature(&self) -> Result<(), InvalidOptionError> {
+        if self.continuations.is_some() || self.overflow_page.is_some() {
+            return Err(InvalidOptionError {
+                field: self.variable.field.clone(),
+                message: "Signatures cannot continue in other boxes or on overflow pages".into(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+// Features of the settings, an invalid one reported as an option of `field`
+fn parse_features(
+    field: &str,
+    settings: &Option<Vec<String>>,
+) -> Result<Vec<Feature>, InvalidOptionError> {
+    settings
+        .iter()
+        .flatten()
+        .map(|setting| {
+            parse_feature(setting).map_err(|err| InvalidOptionError {
+                field: field.to_string(),
+                message: err.0,
+            })
+        })
+        .collect()
+}
+
+// The overflow policies that apply to a whole Markdown document. Documents are not cut short
+// with an ellipsis.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MarkdownOverflow {
+    Visible,
+    Shrink,
+    Clip,
+    Fail,
+}
+
+impl From<MarkdownOverflow> for Overflow {
+    fn from(overflow: MarkdownOverflow) -> Self {
+        match overflow {
+            MarkdownOverflow::Visible => Overflow::Visible,
+            MarkdownOverflow::Shrink => Overflow::Shrink,
+            MarkdownOverflow::Clip => Overflow::Clip,
+            MarkdownOverflow::Fail => Overflow::Fail,
+        }
+    }
+}
+
+// A Markdown document, always wrapped to its box and drawn in a single box. Headings, lists and
+// emphasis come from the value itself.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MarkdownVariable {
+    #[serde(flatten)]
+    pub variable: VariableOptions,
+    pub font_size: Option<f32>,
+    pub align_h: Option<TextAlignment>,
+    pub align_v: Option<VerticalAlign>,
+    pub color: Option<Color>,
+    pub font_type: Option<FontType>,
+    pub font_weight: Option<FontWeight>,
+    pub italic: Option<bool>,
+    // ISO 639-1 code of the language wrapped lines are hyphenated in, no hyphenation if unset
+    pub hyphenation: Option<String>,
+    // Distance between baselines as a multiple of the font size
+    pub line_height: Option<f32>,
+    // Extra space in points after every character and after every space
+    pub letter_spacing: Option<f32>,
+    pub word_spacing: Option<f32>,
+    // Shrink if unset
+    pub overflow: Option<MarkdownOverflow>,
+    // Smallest size the document is shrunk to with the `shrink` overflow policy
+    pub min_font_size: Option<f32>,
+    pub underline: Option<bool>,
+    pub strikethrough: Option<bool>,
+    // Highlight behind each line of text
+    pub background_color: Option<Color>,
+    pub border: Option<Border>,
+    // Stops tab characters advance to. Past the last one tabs advance to every half inch.
+    pub tab_stops: Option<Vec<TabStop>>,
+    // OpenType features, as `smcp`, `tnum`, `onum`, `dlig`, `ss01` or `-liga` to turn one off
+    pub font_features: Option<Vec<String>>,
+    pub render_mode: Option<RenderMode>,
+    // Opacity of the text and its decorations, from 0 (transparent) to 1
+    #[serde(default, deserialize_with = "opacity")]
+    pub opacity: Option<f32>,
+}
+
+impl MarkdownVariable {
+    // OpenType features the document is shaped with
+    pub fn features(&self) -> Result<Vec<Feature>, InvalidOptionError> {
+        parse_features(&self.variable.field, &self.font_features)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -67,6 +369,53 @@ pub struct ImageVariable {
     pub variable: VariableOptions,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MarkVariable {
+    #[serde(flatten)]
+    pub variable: VariableOptions,
+    // Check mark for checkboxes and filled circle for radio buttons if unset
+    pub style: Option<MarkStyle>,
+    pub color: Option<Color>,
+    // Value of the group that selects a radio button. Without one the value is a boolean.
+    pub option: Option<String>,
+}
+
+impl MarkVariable {
+    // Whether the value is a yes, as `true`, `yes`, `on`, `1` or `x`
+    pub fn checked(&self) -> Result<bool, InvalidOptionError> {
+        match self.variable.value.trim().to_ascii_lowercase().as_str() {
+            "true" | "yes" | "on" | "1" | "x" | "checked" => Ok(true),
+            "false" | "no" | "off" | "0" | "" | "unchecked" => Ok(false),
+            value => Err(InvalidOptionError {
+                field: self.variable.field.clone(),
+                message: format!("Value {value} is not a boolean"),
+            }),
+        }
+    }
+
+    // Whether the radio button is the one selected in its group
+    pub fn selected(&self) -> Result<bool, InvalidOptionError> {
+        match &self.option {
+            Some(option) => Ok(self.variable.value == *option),
+            None => self.checked(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QrVariable {
+    #[serde(flatten)]
+    pub variable: VariableOptions,
+    // Encode the verification URL of the generated document instead of the value
+    pub verification_url: Option<bool>,
+    pub error_correction: Option<QrErrorCorrection>,
+    // Margin in modules, 4 if unset and at most 40
+    pub quiet_zone: Option<u32>,
+    pub color: Option<Color>,
+    // Painted under the whole code and its quiet zone, white if unset
+    pub background_color: Option<Color>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum PdfVariable {
@@ -78,6 +427,20 @@ pub enum PdfVariable {
 
     #[serde(rename = "image")]
     Image(ImageVariable),
+
+    // Value written in Markdown, laid out as paragraphs, headings and lists
+    #[serde(rename = "markdown")]
+    Markdown(MarkdownVariable),
+
+    #[serde(rename = "checkbox")]
+    Checkbox(MarkVariable),
+
+    // One button of a radio group, marked when the value is its option
+    #[serde(rename = "radio")]
+    Radio(MarkVariable),
+
+    #[serde(rename = "qr")]
+    Qr(QrVariable),
 }
 
 pub struct PdfVariableList(pub Vec<PdfVariable>);
@@ -107,3 +470,447 @@ pub fn get_page_media_box(
         media_box.get(3).unwrap().as_float()?,
     ))
 }
+
+// Clockwise rotation the page is displayed with, one of 0, 90, 180 or 270. `/Rotate` is inherited
+// from the page tree.
+pub fn get_page_rotation(document: &Document, page: &(u32, u16)) -> Result<i64, BoxedError> {
+    let mut dict = document.get_dictionary(*page)?;
+    loop {
+        if let Ok(rotate) = dict.get(b"Rotate") {
+            return Ok(rotate.as_i64()?.rem_euclid(360) / 90 * 90);
+        }
+        match dict.get(b"Parent").and_then(Object::as_reference) {
+            Ok(parent) => dict = document.get_dictionary(parent)?,
+            Err(_) => return Ok(0),
+        }
+    }
+}
+
+// Width and height of the page as displayed
+pub fn get_page_size(document: &Document, page: &(u32, u16)) -> Result<(f32, f32), BoxedError> {
+    let (x0, y0, x1, y1) = get_page_media_box(document, page)?;
+    let (w, h) = (x1 - x0, y1 - y0);
+
+    match get_page_rotation(document, page)? {
+        90 | 270 => Ok((h, w)),
+        _ => Ok((w, h)),
+    }
+}
+
+// Size of the frame content is laid out in, in a box turned by `rotation` degrees. Past 45
+// degrees content runs along the other side of the box.
+pub fn rotated_size(w: f32, h: f32, rotation: f32) -> (f32, f32) {
+    let radians = rotation.to_radians();
+    if radians.sin().abs() > radians.cos().abs() {
+        (h, w)
+    } else {
+        (w, h)
+    }
+}
+
+// Matrix applying `m` then `n`
+fn multiply(m: [f32; 6], n: [f32; 6]) -> [f32; 6] {
+    [
+        m[0] * n[0] + m[1] * n[2],
+        m[0] * n[1] + m[1] * n[3],
+        m[2] * n[0] + m[3] * n[2],
+        m[2] * n[1] + m[3] * n[3],
+        m[4] * n[0] + m[5] * n[2] + n[4],
+        m[4] * n[1] + m[5] * n[3] + n[5],
+    ]
+}
+
+fn rotation_matrix(degrees: f32) -> [f32; 6] {
+    // Exact for quarter turns, so upright content stays free of rounding noise
+    let snap = |v: f32| if v.abs() < 1e-6 { 0.0 } else { v };
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    [cos, sin, -sin, cos, 0.0, 0.0].map(snap)
+}
+
+// Frame content of a box is drawn in, with its origin in the bottom left corner and `w` by `h`
+// points in size
+pub struct BoxFrame {
+    pub w: f32,
+    pub h: f32,
+    pub matrix: [f32; 6],
+}
+impl BoxFrame {
+    // Operation moving drawing into the frame
+    pub fn transform(&self) -> Operation {
+        Operation::new("cm", self.matrix.iter().map(|&v| v.into()).collect())
+    }
+
+    // Bounding rectangle of the frame in default user space, as x1, y1, x2, y2
+    pub fn bounds(&self) -> [f32; 4] {
+        let [a, b, c, d, e, f] = self.matrix;
+        let corners = [(0.0, 0.0), (self.w, 0.0), (0.0, self.h), (self.w, self.h)]
+            .map(|(x, y)| (a * x + c * y + e, b * x + d * y + f));
+
+        corners.iter().fold(
+            [f32::MAX, f32::MAX, f32::MIN, f32::MIN],
+            |[x1, y1, x2, y2], &(x, y)| [x1.min(x), y1.min(y), x2.max(x), y2.max(y)],
+        )
+    }
+}
+
+// Frame of a box given from the top left corner of the page as displayed, turned `rotation`
+// degrees counterclockwise around its centre
+pub fn get_box_frame(
+    document: &Document,
+    page: &(u32, u16),
+    (x, y, w, h): (f32, f32, f32, f32),
+    rotation: f32,
+) -> Result<BoxFrame, BoxedError> {
+    let (x0, y0, x1, y1) = get_page_media_box(document, page)?;
+    let (_, page_h) = get_page_size(document, page)?;
+    let (frame_w, frame_h) = rotated_size(w, h, rotation);
+
+    // Frame centred on the origin, turned, then moved to the centre of the box
+    let mut matrix = [1.0, 0.0, 0.0, 1.0, -frame_w / 2.0, -frame_h / 2.0];
+    matrix = multiply(matrix, rotation_matrix(rotation));
+    matrix = multiply(
+        matrix,
+        [1.0, 0.0, 0.0, 1.0, x + w / 2.0, page_h - y - h / 2.0],
+    );
+
+    // Displayed page to default user space, undoing the clockwise `/Rotate`
+    let page_rotation = get_page_rotation(document, page)?;
+    let (origin_x, origin_y) = match page_rotation {
+        90 => (x1, y0),
+        180 => (x1, y1),
+        270 => (x0, y1),
+        _ => (x0, y0),
+    };
+    let mut page_matrix = rotation_matrix(page_rotation as f32);
+    page_matrix[4] = origin_x;
+    page_matrix[5] = origin_y;
+
+    Ok(BoxFrame {
+        w: frame_w,
+        h: frame_h,
+        matrix: multiply(matrix, page_matrix),
+    })
+}
+
+// Content streams of each page, taken before anything is drawn so copies of a page can start
+// from the template
+pub fn get_page_contents(document: &Document) -> HashMap<(u32, u16), Object> {
+    document
+        .page_iter()
+        .filter_map(|page| {
+            let contents = document.get_dictionary(page).ok()?.get(b"Contents").ok()?;
+            Some((page, contents.clone()))
+        })
+        .collect()
+}
+
+// Append a copy of a page with the given content streams to the end of the document. The copy
+// shares the resources of the original, inherited attributes are copied onto it since it is added
+// to the root of the page tree.
+pub fn append_page_copy(
+    document: &mut Document,
+    page: &(u32, u16),
+    contents: Option<&Object>,
+) -> Result<(u32, u16), BoxedError> {
+    let mut page_dict = document.get_dictionary(*page)?.clone();
+    match contents {
+        Some(contents) => page_dict.set("Contents", contents.clone()),
+        None => {
+            page_dict.remove(b"Contents");
+        }
+    }
+
+    for key in [b"Resources".as_slice(), b"MediaBox", b"CropBox", b"Rotate"] {
+        let mut parent = page_dict.get(b"Parent").and_then(Object::as_reference).ok();
+        while !page_dict.has(key)
+            && let Some(parent_id) = parent
+        {
+            let parent_dict = document.get_dictionary(parent_id)?;
+            if let Ok(value) = parent_dict.get(key) {
+                page_dict.set(key, value.clone());
+            }
+            parent = parent_dict
+                .get(b"Parent")
+                .and_then(Object::as_reference)
+                .ok();
+        }
+    }
+    // Widgets and links belong to the original page
+    page_dict.remove(b"Annots");
+
+    let pages_id = document.catalog()?.get(b"Pages")?.as_reference()?;
+    page_dict.set("Parent", pages_id);
+    let page_id = document.add_object(page_dict);
+
+    let pages = document.get_object_mut(pages_id)?.as_dict_mut()?;
+    let count = pages.get(b"Count")?.as_i64()?;
+    pages.get_mut(b"Kids")?.as_array_mut()?.push(page_id.into());
+    pages.set("Count", count + 1);
+
+    Ok(page_id)
+}
+
+// Register a graphics state setting the fill and stroke opacity in the resources of the page.
+// Returns the name `gs` selects it by. `field` is the variable reported when the opacity is out of
+// range.
+pub fn add_opacity_state(
+    document: &mut Document,
+    page: &(u32, u16),
+    field: &str,
+    opacity: f32,
+) -> Result<String, BoxedError> {
+    if !(0.0..=1.0).contains(&opacity) {
+        return Err(InvalidOptionError {
+            field: field.to_string(),
+            message: format!("Opacity {opacity} is not between 0 and 1"),
+        }
+        .into());
+    }
+
+    // Opacities are told apart to a thousandth, texts with the same one share its state
+    let thousandths = (opacity * 1000.0).round();
+    let name = format!("pdf-Opacity{thousandths}");
+
+    let mut resources_dict = document
+        .get_or_create_resources(*page)?
+        .as_dict_mut()?
+        .clone();
+    let mut states = match resources_dict.get(b"ExtGState") {
+        Ok(Object::Reference(states_id)) => document.get_object(*states_id)?.as_dict()?.clone(),
+        Ok(Object::Dictionary(states)) => states.clone(),
+        _ => Dictionary::new(),
+    };
+    if states.has(name.as_bytes()) {
+        return Ok(name);
+    }
+
+    let opacity = thousandths / 1000.0;
+    let state_id = document.add_object(dictionary! {
+        "Type" => "ExtGState",
+        "ca" => opacity,
+        "CA" => opacity,
+    });
+    states.set(name.as_str(), state_id);
+    resources_dict.set("ExtGState", states);
+
+    document
+        .get_object_mut(*page)?
+        .as_dict_mut()?
+        .set("Resources", resources_dict);
+
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Document with a single 200 by 100 point page, `page` holding extra page attributes and
+    // `pages` extra attributes of its parent
+    fn document_with_page(page: Dictionary, pages: Dictionary) -> (Document, (u32, u16)) {
+        let mut document = Document::with_version("1.7");
+        let pages_id = document.new_object_id();
+        let mut page_dict = dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => vec![0.into(), 0.into(), 200.into(), 100.into()],
+        };
+        page_dict.extend(&page);
+        let page_id = document.add_object(page_dict);
+        let mut pages_dict = dictionary! {
+            "Type" => "Pages",
+            "Kids" => vec![page_id.into()],
+            "Count" => 1,
+        };
+        pages_dict.extend(&pages);
+        document.objects.insert(pages_id, pages_dict.into());
+
+        (document, page_id)
+    }
+
+    fn assert_bounds(actual: [f32; 4], expected: [f32; 4]) {
+        assert!(
+            actual
+                .iter()
+                .zip(expected)
+                .all(|(a, e)| (a - e).abs() < 1e-3),
+            "{actual:?} != {expected:?}"
+        );
+    }
+
+    #[test]
+    fn box_frame_measures_from_the_top_left_corner() {
+        let (document, page) = document_with_page(Dictionary::new(), Dictionary::new());
+
+        let frame = get_box_frame(&document, &page, (10.0, 20.0, 30.0, 40.0), 0.0).unwrap();
+
+        assert_eq!((frame.w, frame.h), (30.0, 40.0));
+        assert_bounds(frame.bounds(), [10.0, 40.0, 40.0, 80.0]);
+    }
+
+    #[test]
+    fn box_frame_follows_the_displayed_page_of_a_rotated_page() {
+        let (document, page) =
+            document_with_page(dictionary! { "Rotate" => 90 }, Dictionary::new());
+
+        let frame = get_box_frame(&document, &page, (10.0, 20.0, 30.0, 40.0), 0.0).unwrap();
+
+        assert_eq!(get_page_size(&document, &page).unwrap(), (100.0, 200.0));
+        assert_bounds(frame.bounds(), [20.0, 10.0, 60.0, 40.0]);
+    }
+
+    #[test]
+    fn box_frame_inherits_the_page_rotation() {
+        let (document, page) =
+            document_with_page(Dictionary::new(), dictionary! { "Rotate" => -270 });
+
+        let frame = get_box_frame(&document, &page, (10.0, 20.0, 30.0, 40.0), 0.0).unwrap();
+
+        assert_bounds(frame.bounds(), [20.0, 10.0, 60.0, 40.0]);
+    }
+
+    #[test]
+    fn box_frame_turns_content_around_the_box_centre() {
+        let (document, page) = document_with_page(Dictionary::new(), Dictionary::new());
+
+        let frame = get_box_frame(&document, &page, (10.0, 20.0, 30.0, 40.0), 90.0).unwrap();
+
+        // The frame runs along the other side of the box and covers the same area
+        assert_eq!((frame.w, frame.h), (40.0, 30.0));
+        assert_bounds(frame.bounds(), [10.0, 40.0, 40.0, 80.0]);
+    }
+
+    fn mark(value: &str, option: Option<&str>) -> MarkVariable {
+        let json = serde_json::json!({
+            "type": "radio",
+            "x": 0, "y": 0, "w": 10, "h": 10, "page": 1,
+            "field": "choice",
+            "value": value,
+            "option": option,
+        });
+        match serde_json::from_value(json).unwrap() {
+            PdfVariable::Radio(mark) => mark,
+            variable => panic!("Unexpected variable {variable:?}"),
+        }
+    }
+
+    #[test]
+    fn checked_reads_yes_and_no_values() {
+        for value in ["true", "Yes", " on ", "1", "X", "checked"] {
+            assert!(mark(value, None).checked().unwrap(), "{value}");
+        }
+        for value in ["false", "NO", "off", "0", "", "unchecked"] {
+            assert!(!mark(value, None).checked().unwrap(), "{value}");
+        }
+        assert_eq!(mark("maybe", None).checked().unwrap_err().field, "choice");
+    }
+
+    #[test]
+    fn selected_compares_the_value_to_the_option() {
+        assert!(mark("blue", Some("blue")).selected().unwrap());
+        assert!(!mark("red", Some("blue")).selected().unwrap());
+        // Without an option the value is a boolean
+        assert!(mark("yes", None).selected().unwrap());
+        assert_eq!(mark("maybe", None).selected().unwrap_err().field, "choice");
+    }
+
+    #[test]
+    fn checkbox_values_can_be_json_booleans() {
+        for (value, checked) in [(true, true), (false, false)] {
+            let json = serde_json::json!({
+                "type": "checkbox",
+                "x": 0, "y": 0, "w": 10, "h": 10, "page": 1,
+                "field": "agree",
+                "value": value,
+            });
+            let PdfVariable::Checkbox(mark) = serde_json::from_value(json).unwrap() else {
+                panic!("Not a checkbox");
+            };
+            assert_eq!(mark.checked().unwrap(), checked);
+        }
+    }
+
+    #[test]
+    fn opacity_out_of_range_is_rejected_when_read() {
+        let text = |opacity: f32| {
+            serde_json::from_value::<PdfVariable>(serde_json::json!({
+                "type": "text",
+                "x": 0, "y": 0, "w": 10, "h": 10, "page": 1,
+                "field": "name",
+                "value": "Ada",
+                "opacity": opacity,
+            }))
+        };
+
+        assert!(text(0.5).is_ok());
+        let err = text(1.5).unwrap_err();
+        assert!(err.to_string().contains("not between 0 and 1"), "{err}");
+    }
+
+    #[test]
+    fn opacity_out_of_range_names_the_field() {
+        let (mut document, page) = document_with_page(Dictionary::new(), Dictionary::new());
+
+        let err = add_opacity_state(&mut document, &page, "name", -0.5).unwrap_err();
+
+        let err = err.downcast_ref::<InvalidOptionError>().unwrap();
+        assert_eq!(err.field, "name");
+    }
+
+    #[test]
+    fn invalid_font_features_name_the_field() {
+        let json = serde_json::json!({
+            "type": "markdown",
+            "x": 0, "y": 0, "w": 10, "h": 10, "page": 1,
+            "field": "notes",
+            "value": "Notes",
+            "font_features": ["smcp", "not a feature"],
+        });
+        let PdfVariable::Markdown(markdown) = serde_json::from_value(json).unwrap() else {
+            panic!("Not a Markdown variable");
+        };
+
+        assert_eq!(markdown.features().unwrap_err().field, "notes");
+    }
+
+    #[test]
+    fn invalid_markup_names_the_field() {
+        let json = serde_json::json!({
+            "type": "text",
+            "x": 0, "y": 0, "w": 10, "h": 10, "page": 1,
+            "field": "name",
+            "value": "<b>Ada",
+            "markup": "html",
+        });
+        let PdfVariable::Text(text) = serde_json::from_value(json).unwrap() else {
+            panic!("Not a text variable");
+        };
+
+        assert_eq!(text.rich_text().unwrap_err().field, "name");
+    }
+
+    #[test]
+    fn signatures_cannot_continue_in_other_boxes() {
+        let signature = |extra: serde_json::Value| {
+            let mut json = serde_json::json!({
+                "type": "signature",
+                "x": 0, "y": 0, "w": 10, "h": 10, "page": 1,
+                "field": "signed",
+                "value": "Ada",
+            });
+            json.as_object_mut()
+                .unwrap()
+                .extend(extra.as_object().unwrap().clone());
+            let PdfVariable::Signature(signature) = serde_json::from_value(json).unwrap() else {
+                panic!("Not a signature");
+            };
+            signature
+        };
+
+        assert!(signature(serde_json::json!({})).check_signature().is_ok());
+        let continued = signature(serde_json::json!({
+            "continuations": [{ "page": 2, "x": 0, "y": 0, "w": 10, "h": 10 }],
+        }));
+        assert_eq!(continued.check_signature().unwrap_err().field, "signed");
+    }
+}
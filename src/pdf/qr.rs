@@ -0,0 +1,236 @@
+use lopdf::{
+    Document,
+    content::{Content, Operation},
+};
+use qrcode::{EcLevel, QrCode};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{BoxedError, InvalidOptionError};
+
+use super::{color::Color, get_box_frame};
+
+// Light modules around the code scanners need to find it, per the QR specification
+pub const DEFAULT_QUIET_ZONE: u32 = 4;
+// Widest quiet zone accepted, far more than any scanner needs
+pub const MAX_QUIET_ZONE: u32 = 40;
+
+// Share of the code that can be damaged and still be read
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QrErrorCorrection {
+    // 7%
+    Low,
+    // 15%
+    #[default]
+    Medium,
+    // 25%
+    Quartile,
+    // 30%
+    High,
+}
+
+impl From<QrErrorCorrection> for EcLevel {
+    fn from(level: QrErrorCorrection) -> Self {
+        match level {
+            QrErrorCorrection::Low => EcLevel::L,
+            QrErrorCorrection::Medium => EcLevel::M,
+            QrErrorCorrection::Quartile => EcLevel::Q,
+            QrErrorCorrection::High => EcLevel::H,
+        }
+    }
+}
+
+pub struct DrawQrOptions<'a> {
+    // Name of the variable the code comes from, reported when it cannot be drawn
+    pub field: &'a str,
+    pub data: &'a str,
+    pub error_correction: QrErrorCorrection,
+    // Width of the margin in modules
+    pub quiet_zone: u32,
+    // Color of the dark modules, black if unset
+    pub color: Option<Color>,
+    // Color of the light modules and the quiet zone, white if unset, so the code scans on any
+    // background
+    pub background_color: Option<Color>,
+    pub x: f32,
+    pub y: f32,
+    pub w: f32,
+    pub h: f32,
+    // Degrees counterclockwise, the code is drawn in the turned box
+    pub rotation: f32,
+}
+
+// Runs of dark modules along the rows of a code `width` modules wide, as their row, first column
+// and length
+fn dark_runs(colors: &[qrcode::Color], width: usize) -> Vec<(usize, usize, usize)> {
+    let mut runs = vec![];
+    for (row, modules_of_row) in colors.chunks(width).enumerate() {
+        let mut column = 0;
+        while column < width {
+            if modules_of_row[column] == qrcode::Color::Light {
+                column += 1;
+                continue;
+            }
+
+            let start = column;
+            while column < width && modules_of_row[column] == qrcode::Color::Dark {
+                column += 1;
+            }
+            runs.push((row, start, column - start));
+        }
+    }
+
+    runs
+}
+
+// Draw the data as a QR code centred in the box, as large as its shorter side including the quiet
+// zone. The whole code is painted in the background color first, then dark modules are filled as
+// rectangles, one per run of modules along a row.
+pub fn draw_qr(
+    document: &mut Document,
+    page: &(u32, u16),
+    options: DrawQrOptions,
+) -> Result<(), BoxedError> {
+    if options.quiet_zone > MAX_QUIET_ZONE {
+        return Err(InvalidOptionError {
+            field: options.field.to_string(),
+            message: format!("Quiet zone is wider than {MAX_QUIET_ZONE} modules"),
+        }
+        .into());
+    }
+
+    let encoded =
+        QrCode::with_error_correction_level(options.data, options.error_correction.into());
+    let code = encoded.map_err(|err| InvalidOptionError {
+        field: options.field.to_string(),
+        message: format!("Cannot encode QR code: {err}"),
+    })?;
+    let frame = get_box_frame(
+        document,
+        page,
+        (options.x, options.y, options.w, options.h),
+        options.rotation,
+    )?;
+
+    let width = code.width();
+    let modules = (width as u32 + 2 * options.quiet_zone) as f32;
+    let size = frame.w.min(frame.h);
+    let module_size = size / modules;
+    let color = options.color.unwrap_or(Color::from_rgb(0, 0, 0));
+    let background_color = options
+        .background_color
+        .unwrap_or(Color::from_rgb(0xFF, 0xFF, 0xFF));
+    let rgb = |color: Color| vec![color.r().into(), color.g().into(), color.b().into()];
+
+    let mut content = Content {
+        operations: vec![
+            Operation::new("q", vec![]), // Save graphics state
+            frame.transform(),           // Into the box
+            Operation::new(
+                "cm",
+                vec![
+                    module_size.into(),
+                    0.into(),
+                    0.into(),
+                    module_size.into(),
+                    ((frame.w - size) / 2.0).into(),
+                    ((frame.h - size) / 2.0).into(),
+                ],
+            ), // Into the module grid, one unit per module
+            Operation::new("rg", rgb(background_color)), // Background color
+            Operation::new(
+                "re",
+                vec![0.into(), 0.into(), modules.into(), modules.into()],
+            ),
+            Operation::new("f", vec![]),      // Fill background
+            Operation::new("rg", rgb(color)), // Color
+        ],
+    };
+
+    let quiet_zone = options.quiet_zone as f32;
+    for (row, start, length) in dark_runs(&code.to_colors(), width) {
+        // Rows run top to bottom, the grid bottom to top
+        let y = quiet_zone + (width - row - 1) as f32;
+        content.operations.push(Operation::new(
+            "re",
+            vec![
+                (quiet_zone + start as f32).into(),
+                y.into(),
+                (length as f32).into(),
+                1.into(),
+            ],
+        ));
+    }
+    content.operations.push(Operation::new("f", vec![])); // Fill modules
+    content.operations.push(Operation::new("Q", vec![])); // Restore graphics state
+
+    document.add_page_contents(page.to_owned(), content.encode()?)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use qrcode::Color::{Dark, Light};
+
+    #[test]
+    fn dark_runs_merge_neighbouring_modules_of_a_row() {
+        let colors = [
+            Dark, Dark, Light, Dark, //
+            Light, Light, Light, Light, //
+            Light, Dark, Dark, Dark, //
+        ];
+
+        assert_eq!(dark_runs(&colors, 4), [(0, 0, 2), (0, 3, 1), (2, 1, 3)]);
+    }
+
+    #[test]
+    fn dark_runs_cover_every_dark_module_of_a_code() {
+        let code = QrCode::new("https://example.com").unwrap();
+        let width = code.width();
+        let colors = code.to_colors();
+
+        let runs = dark_runs(&colors, width);
+
+        // The top edge of the top left finder pattern is one run of seven modules
+        assert_eq!(runs[0], (0, 0, 7));
+        let dark = colors.iter().filter(|color| **color == Dark).count();
+        assert_eq!(
+            runs.iter().map(|(_, _, length)| length).sum::<usize>(),
+            dark
+        );
+        assert!(
+            runs.iter()
+                .all(|(_, start, length)| start + length <= width)
+        );
+    }
+
+    #[test]
+    fn data_too_long_to_encode_names_the_field() {
+        let mut document = Document::with_version("1.7");
+        let data = "x".repeat(4000);
+
+        let err = draw_qr(
+            &mut document,
+            &(1, 0),
+            DrawQrOptions {
+                field: "link",
+                data: &data,
+                error_correction: QrErrorCorrection::High,
+                quiet_zone: DEFAULT_QUIET_ZONE,
+                color: None,
+                background_color: None,
+                x: 0.0,
+                y: 0.0,
+                w: 100.0,
+                h: 100.0,
+                rotation: 0.0,
+            },
+        )
+        .unwrap_err();
+
+        let err = err.downcast_ref::<InvalidOptionError>().unwrap();
+        assert_eq!(err.field, "link");
+    }
+}
@@ -1,52 +1,189 @@
 use lopdf::{
-    Document, Object,
+    Document, Object, StringFormat,
     content::{Content, Operation},
 };
 
-use rustybuzz::{GlyphBuffer, UnicodeBuffer};
+use hypher::Lang;
+use rustybuzz::{Face, Feature, GlyphBuffer};
+use unicode_segmentation::UnicodeSegmentation;
 
 use crate::{
-    error::{BoxedError, GenericError},
-    fonts::{
-        FontType, FontWeight, fit_text_font_size, get_font, get_font_pdf_name, text_width,
-        wrap_text,
+    error::{
+        BoxedError, CombOverflowError, InvalidOptionError, OverflowPageLimitError,
+        TextOverflowError,
+    },
+    fonts::{FontType, FontWeight, PdfFont, Spacing, get_font, is_rtl, shape_text_segments},
+    pdf::{
+        Border, Comb, MarkdownVariable, Overflow, RenderMode, TabStop, TextAlignment, TextVariable,
+        VerticalAlign, add_opacity_state, append_page_copy,
+        font::FontRegistry,
+        layout::{
+            LayoutOptions, MIN_FONT_SIZE, RunStyle, StyledRange, TextLayout, hyphenation_lang,
+            layout_comb, layout_text, layout_text_part, style_of,
+        },
+        markdown::{MarkdownBlock, layout_markdown},
+        markup::{RichText, StyledSpan, skip_spans},
     },
-    pdf::{TextAlignment, VerticalAlign},
 };
 
-use super::{color::Color, get_page_media_box};
+use super::{color::Color, get_box_frame, rotated_size};
+
+// Pair each shaped glyph with the text it was shaped from. When several glyphs share a cluster
+// the first one carries the text and the rest map to an empty string.
+fn glyph_texts(text: &str, glyph_buffer: &GlyphBuffer) -> Vec<(u16, String)> {
+    let mut clusters: Vec<usize> = glyph_buffer
+        .glyph_infos()
+        .iter()
+        .map(|info| info.cluster as usize)
+        .collect();
+    clusters.sort_unstable();
+    clusters.dedup();
 
-// Hard wrap a string by character count, preferably breaking on whitespace.
-pub fn hard_wrap_by_chars(text: &str, max_chars: usize) -> String {
-    let mut out = String::with_capacity(text.len() + text.len() / max_chars.max(1));
-    let mut line_char_count = 0;
+    let mut seen_clusters = vec![];
+    glyph_buffer
+        .glyph_infos()
+        .iter()
+        .map(|info| {
+            let start = info.cluster as usize;
+            if seen_clusters.contains(&start) {
+                return (info.glyph_id as u16, String::new());
+            }
+            seen_clusters.push(start);
+
+            let end = clusters
+                .iter()
+                .find(|cluster| **cluster > start)
+                .copied()
+                .unwrap_or(text.len());
+            (info.glyph_id as u16, text[start..end].to_string())
+        })
+        .collect()
+}
 
-    for ch in text.chars() {
-        if ch == '\n' {
-            line_char_count = 0;
-            out.push('\n');
-            continue;
+// Write a shaped glyph run as TJ arrays. The font's widths only hold the default advance of
+// each glyph, so every glyph is preceded by the adjustment that moves the pen to the position
+// the shaper chose. Vertical offsets are applied with the text rise. Letter spacing is set with
+// `Tc` by the caller, which also spaces marks, so the adjustments pull marks back onto their
+// base. Word spacing is written as adjustments too, `Tw` does not apply to the two byte codes of
+// Type0 fonts.
+fn glyph_run_operations(
+    face: &Face,
+    text: &str,
+    glyph_buffer: &GlyphBuffer,
+    font_size: f32,
+    spacing: Spacing,
+) -> Vec<Operation> {
+    let upem = face.units_per_em() as f32;
+    let letter_spacing = spacing.letter * upem / font_size;
+    let word_spacing = spacing.word * upem / font_size;
+    let mut operations = vec![];
+    let mut run: Vec<Object> = vec![];
+    let mut glyph_ids: Vec<u8> = vec![];
+
+    let mut rise = 0;
+    // Where the shaper places the next glyph, and where the PDF pen currently is
+    let mut pen_x = 0f32;
+    let mut pdf_x = 0f32;
+
+    for (info, position) in glyph_buffer
+        .glyph_infos()
+        .iter()
+        .zip(glyph_buffer.glyph_positions())
+    {
+        if position.y_offset != rise {
+            if !glyph_ids.is_empty() {
+                run.push(Object::String(
+                    std::mem::take(&mut glyph_ids),
+                    StringFormat::Hexadecimal,
+                ));
+            }
+            if !run.is_empty() {
+                operations.push(Operation::new("TJ", vec![Object::Array(run)]));
+                run = vec![];
+            }
+            rise = position.y_offset;
+            operations.push(Operation::new(
+                "Ts",
+                vec![(rise as f32 * font_size / upem).into()],
+            ));
         }
 
-        // if about to exceed limit, break line even without space
-        if line_char_count >= max_chars {
-            out.push('\n');
-            line_char_count = 0;
+        let glyph_x = pen_x + position.x_offset as f32;
+        let shift = glyph_x - pdf_x;
+        if shift.abs() > 0.5 {
+            if !glyph_ids.is_empty() {
+                run.push(Object::String(
+                    std::mem::take(&mut glyph_ids),
+                    StringFormat::Hexadecimal,
+                ));
+            }
+            // TJ adjustments are subtracted from the pen position, in thousandths of an em
+            run.push((-shift * 1000.0 / upem).into());
         }
 
-        out.push(ch);
-        line_char_count += 1;
+        glyph_ids.extend((info.glyph_id as u16).to_be_bytes());
+
+        let width = face
+            .glyph_hor_advance(rustybuzz::ttf_parser::GlyphId(info.glyph_id as u16))
+            .unwrap_or(0);
+        pdf_x = glyph_x + width as f32 + letter_spacing;
+        pen_x += position.x_advance as f32;
+        if position.x_advance != 0 {
+            pen_x += letter_spacing;
+        }
+        if text[info.cluster as usize..].starts_with(' ') {
+            pen_x += word_spacing;
+        }
     }
 
-    out
+    if !glyph_ids.is_empty() {
+        run.push(Object::String(glyph_ids, StringFormat::Hexadecimal));
+    }
+    // Leave the pen where the shaper ends the run so a following run continues from there
+    let shift = pen_x - pdf_x;
+    if shift.abs() > 0.5 {
+        run.push((-shift * 1000.0 / upem).into());
+    }
+    if !run.is_empty() {
+        operations.push(Operation::new("TJ", vec![Object::Array(run)]));
+    }
+    if rise != 0 {
+        operations.push(Operation::new("Ts", vec![0.into()]));
+    }
+
+    operations
+}
+
+// Lines and fills drawn with the text, underline and strikethrough in the text color
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TextDecoration {
+    pub underline: bool,
+    pub strikethrough: bool,
+    pub background_color: Option<Color>,
+    pub border: Option<Border>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Default)]
 pub struct DrawTextOptions<'a> {
+    // Name of the variable the text comes from, reported when it cannot be drawn
+    pub field: &'a str,
     pub text: &'a str,
+    // Formatting of spans of `text`, over the font options below
+    pub styles: &'a [StyledSpan],
     pub font_size: f32,
     pub font_weight: Option<FontWeight>,
     pub font_type: Option<FontType>,
+    pub italic: bool,
+    pub hyphenation: Option<Lang>,
+    // Multiple of the font size, the face's own line spacing if unset
+    pub line_height: Option<f32>,
+    pub spacing: Spacing,
+    pub decoration: TextDecoration,
+    pub render_mode: RenderMode,
+    // Opacity of everything drawn, opaque if unset
+    pub opacity: Option<f32>,
+    pub overflow: Option<Overflow>,
+    pub min_font_size: Option<f32>,
     pub text_alignment: Option<TextAlignment>,
     pub align_v: Option<VerticalAlign>,
     pub color: Option<Color>,
@@ -54,85 +191,498 @@ pub struct DrawTextOptions<'a> {
     pub y: f32,
     pub w: f32,
     pub h: f32,
+    // Degrees counterclockwise, text is laid out in the turned box
+    pub rotation: f32,
+    pub tab_stops: &'a [TabStop],
+    // One character per cell instead of lines of text
+    pub comb: Option<Comb>,
+    // OpenType features text is measured and drawn with
+    pub features: &'a [Feature],
 }
-pub fn draw_text<'a>(
-    document: &'a mut Document,
+impl<'a> DrawTextOptions<'a> {
+    // Options of a text variable, with `rich_text` and `features` parsed from it. Text is set in
+    // `font_size` unless the variable has its own size.
+    pub fn from_variable(
+        variable: &'a TextVariable,
+        rich_text: &'a RichText,
+        features: &'a [Feature],
+        font_size: f32,
+    ) -> Result<Self, BoxedError> {
+        Ok(DrawTextOptions {
+            field: &variable.variable.field,
+            text: &rich_text.text,
+            styles: &rich_text.spans,
+            font_size: variable.font_size.unwrap_or(font_size),
+            font_weight: variable.font_weight,
+            font_type: variable.font_type.or(Some(FontType::SansSerif)),
+            italic: variable.italic.unwrap_or(false),
+            hyphenation: variable
+                .hyphenation
+                .as_deref()
+                .map(|code| hyphenation_lang(&variable.variable.field, code))
+                .transpose()?,
+            line_height: variable.line_height,
+            spacing: Spacing {
+                letter: variable.letter_spacing.unwrap_or(0.0),
+                word: variable.word_spacing.unwrap_or(0.0),
+            },
+            decoration: TextDecoration {
+                underline: variable.underline.unwrap_or(false),
+                strikethrough: variable.strikethrough.unwrap_or(false),
+                background_color: variable.background_color,
+                border: variable.border,
+            },
+            render_mode: variable.render_mode.unwrap_or_default(),
+            opacity: variable.opacity,
+            overflow: variable.overflow,
+            min_font_size: variable.min_font_size,
+            text_alignment: variable.align_h.clone(),
+            align_v: variable.align_v.clone(),
+            color: variable.color,
+            x: variable.variable.x,
+            y: variable.variable.y,
+            w: variable.variable.w,
+            h: variable.variable.h,
+            rotation: variable.variable.rotation.unwrap_or(0.0),
+            tab_stops: variable.tab_stops.as_deref().unwrap_or(&[]),
+            comb: variable.comb,
+            features,
+        })
+    }
+
+    // Options of a Markdown variable, whose blocks carry their own styles
+    pub fn from_markdown_variable(
+        variable: &'a MarkdownVariable,
+        features: &'a [Feature],
+        font_size: f32,
+    ) -> Result<Self, BoxedError> {
+        Ok(DrawTextOptions {
+            field: &variable.variable.field,
+            text: &variable.variable.value,
+            font_size: variable.font_size.unwrap_or(font_size),
+            font_weight: variable.font_weight,
+            font_type: variable.font_type.or(Some(FontType::SansSerif)),
+            italic: variable.italic.unwrap_or(false),
+            hyphenation: variable
+                .hyphenation
+                .as_deref()
+                .map(|code| hyphenation_lang(&variable.variable.field, code))
+                .transpose()?,
+            line_height: variable.line_height,
+            spacing: Spacing {
+                letter: variable.letter_spacing.unwrap_or(0.0),
+                word: variable.word_spacing.unwrap_or(0.0),
+            },
+            decoration: TextDecoration {
+                underline: variable.underline.unwrap_or(false),
+                strikethrough: variable.strikethrough.unwrap_or(false),
+                background_color: variable.background_color,
+                border: variable.border,
+            },
+            render_mode: variable.render_mode.unwrap_or_default(),
+            opacity: variable.opacity,
+            overflow: variable.overflow.map(Overflow::from),
+            min_font_size: variable.min_font_size,
+            text_alignment: variable.align_h.clone(),
+            align_v: variable.align_v.clone(),
+            color: variable.color,
+            x: variable.variable.x,
+            y: variable.variable.y,
+            w: variable.variable.w,
+            h: variable.variable.h,
+            rotation: variable.variable.rotation.unwrap_or(0.0),
+            tab_stops: variable.tab_stops.as_deref().unwrap_or(&[]),
+            features,
+            ..Default::default()
+        })
+    }
+
+    fn resolved_font_type(&self) -> FontType {
+        self.font_type.unwrap_or(FontType::Serif)
+    }
+
+    fn resolved_font_weight(&self) -> FontWeight {
+        self.font_weight.unwrap_or(FontWeight::Regular)
+    }
+
+    // The font text is measured with. It is always the font embedded and painted, so layout
+    // computed from it matches what ends up on the page.
+    pub fn font(&self) -> PdfFont {
+        get_font(
+            &self.resolved_font_type(),
+            &self.resolved_font_weight(),
+            self.italic,
+        )
+    }
+
+    // Explicit alignment, or the natural alignment of the paragraph direction
+    fn resolved_text_alignment(&self) -> TextAlignment {
+        self.text_alignment.clone().unwrap_or(if is_rtl(self.text) {
+            TextAlignment::Right
+        } else {
+            TextAlignment::Left
+        })
+    }
+
+    // Width and height of the box along and across the lines
+    pub fn frame_size(&self) -> (f32, f32) {
+        rotated_size(self.w, self.h, self.rotation)
+    }
+
+    // Layout of the text in its box. `font_size` is the size text is drawn at or shrunk from,
+    // `overflow` the policy used when the options do not set one.
+    pub fn layout_options(&self, font_size: f32, wrap: bool, overflow: Overflow) -> LayoutOptions {
+        let (w, h) = self.frame_size();
+        let styles = self
+            .styles
+            .iter()
+            .map(|span| StyledRange {
+                range: span.range.clone(),
+                style: RunStyle {
+                    font: get_font(
+                        &self.resolved_font_type(),
+                        &span
+                            .style
+                            .font_weight
+                            .unwrap_or(self.resolved_font_weight()),
+                        span.style.italic.unwrap_or(self.italic),
+                    ),
+                    scale: span
+                        .style
+                        .font_size
+                        .map_or(1.0, |span_size| span_size / self.font_size),
+                    color: span.style.color,
+                },
+            })
+            .collect();
+
+        LayoutOptions {
+            font: self.font(),
+            styles,
+            font_size,
+            min_font_size: self.min_font_size.unwrap_or(MIN_FONT_SIZE),
+            overflow: self.overflow.unwrap_or(overflow),
+            wrap,
+            hyphenation: self.hyphenation,
+            text_alignment: self.resolved_text_alignment(),
+            align_v: self.align_v.clone().unwrap_or(VerticalAlign::Top),
+            line_height: self.line_height,
+            spacing: self.spacing,
+            tab_stops: self.tab_stops.to_vec(),
+            features: self.features.to_vec(),
+            w,
+            h,
+        }
+    }
+}
+
+fn overflow_error(options: &DrawTextOptions, layout: &TextLayout) -> TextOverflowError {
+    let (box_width, box_height) = options.frame_size();
+    TextOverflowError {
+        field: options.field.to_string(),
+        font_size: layout.font_size,
+        text_width: layout.width,
+        text_height: layout.height,
+        box_width,
+        box_height,
+    }
+}
+
+// A comb holds at most one character per cell
+fn check_comb(options: &DrawTextOptions, comb: Comb) -> Result<(), BoxedError> {
+    let characters = options.text.graphemes(true).count();
+    if comb.cells == 0 {
+        return Err(InvalidOptionError {
+            field: options.field.to_string(),
+            message: "Comb has no cells".into(),
+        }
+        .into());
+    }
+    if characters > comb.cells {
+        return Err(CombOverflowError {
+            field: options.field.to_string(),
+            characters,
+            cells: comb.cells,
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+// Lay out text in the box of `options` and paint it, applying the overflow policy
+fn draw_text_box(
+    document: &mut Document,
+    fonts: &mut FontRegistry,
     page: &(u32, u16),
-    options: DrawTextOptions<'a>,
+    options: &DrawTextOptions,
+    layout_options: LayoutOptions,
 ) -> Result<(), BoxedError> {
-    let (_, _, _page_w, page_h) = get_page_media_box(document, page)?;
-
-    let color = options.color.unwrap_or(Color::from_rgb(0, 0, 0));
+    let layout = match options.comb {
+        Some(comb) => {
+            check_comb(options, comb)?;
+            layout_comb(
+                options.text,
+                &layout_options,
+                comb.cells,
+                comb.spacing.unwrap_or(0.0),
+            )
+        }
+        None => layout_text(options.text, &layout_options),
+    };
+    if layout.overflows && matches!(layout_options.overflow, Overflow::Fail) {
+        return Err(overflow_error(options, &layout).into());
+    }
 
-    let font_weight = options.font_weight.unwrap_or(FontWeight::Regular);
-    let font_type = options.font_type.unwrap_or(FontType::Serif);
+    let clip = matches!(layout_options.overflow, Overflow::Clip);
+    draw_layout(document, fonts, page, options, &layout, clip)
+}
 
-    let font_face = get_font(&font_type, &font_weight, false);
-    let font_name = get_font_pdf_name(&font_type, &font_weight, false);
+// Line width of outlined text, in ems
+const OUTLINE_WIDTH: f32 = 0.03;
 
-    let upem = font_face.units_per_em() as f32;
-    let scale = options.font_size / upem;
+// Position and thickness of the strokes through lines, from the baseline in points
+struct DecorationMetrics {
+    underline: (f32, f32),
+    strikethrough: (f32, f32),
+}
 
-    let mut buffer = UnicodeBuffer::new();
-    buffer.push_str(options.text);
-    let glyph_buffer: GlyphBuffer = rustybuzz::shape(font_face, &[], buffer);
+impl DecorationMetrics {
+    fn new(face: &Face, font_size: f32) -> Self {
+        let scale = font_size / face.units_per_em() as f32;
+        let stroke = |metrics: Option<ttf_parser::LineMetrics>, position: f32| {
+            metrics
+                .filter(|metrics| metrics.thickness > 0)
+                .map(|metrics| {
+                    (
+                        metrics.position as f32 * scale,
+                        metrics.thickness as f32 * scale,
+                    )
+                })
+                .unwrap_or((position * font_size, 0.05 * font_size))
+        };
 
-    let mut content = Content { operations: vec![] };
+        DecorationMetrics {
+            underline: stroke(face.underline_metrics(), -0.1),
+            strikethrough: stroke(face.strikeout_metrics(), 0.3),
+        }
+    }
+}
 
-    content.operations.push(Operation::new(
+fn fill_color(color: Color) -> Operation {
+    Operation::new(
         "rg",
         vec![color.r().into(), color.g().into(), color.b().into()],
-    )); // Color
+    )
+}
+
+fn stroke_color(color: Color) -> Operation {
+    Operation::new(
+        "RG",
+        vec![color.r().into(), color.g().into(), color.b().into()],
+    )
+}
+
+fn rectangle(x: f32, y: f32, w: f32, h: f32) -> Operation {
+    Operation::new("re", vec![x.into(), y.into(), w.into(), h.into()])
+}
+
+// Paint laid out lines, positioned relative to the box of `options`
+fn draw_layout(
+    document: &mut Document,
+    fonts: &mut FontRegistry,
+    page: &(u32, u16),
+    options: &DrawTextOptions,
+    layout: &TextLayout,
+    clip: bool,
+) -> Result<(), BoxedError> {
+    let frame = get_box_frame(
+        document,
+        page,
+        (options.x, options.y, options.w, options.h),
+        options.rotation,
+    )?;
+
+    let color = options.color.unwrap_or(Color::from_rgb(0, 0, 0));
+    let font = options.font();
+
+    let mut content = Content { operations: vec![] };
+
+    content.operations.push(Operation::new("q", vec![])); // Save graphics state
+    content.operations.push(frame.transform()); // Into the box
+    if let Some(opacity) = options.opacity {
+        let state = add_opacity_state(document, page, options.field, opacity)?;
+        content
+            .operations
+            .push(Operation::new("gs", vec![Object::Name(state.into_bytes())])); // Opacity
+    }
+    content.operations.push(Operation::new("q", vec![]));
+    if clip {
+        content.operations.push(Operation::new(
+            "re",
+            vec![0.into(), 0.into(), frame.w.into(), frame.h.into()],
+        ));
+        content.operations.push(Operation::new("W", vec![])); // Clip to the box
+        content.operations.push(Operation::new("n", vec![]));
+    }
+
+    let decoration = options.decoration;
+    if let Some(background) = decoration.background_color {
+        content.operations.push(fill_color(background)); // Highlight color
+        for line in layout.lines.iter().filter(|line| line.width > 0.0) {
+            let bottom = frame.h - line.baseline - line.descent;
+            content.operations.push(rectangle(
+                line.x,
+                bottom,
+                line.width,
+                line.ascent + line.descent,
+            ));
+        }
+        content.operations.push(Operation::new("f", vec![])); // Fill highlights
+    }
+
+    let render_mode = options.render_mode;
+    content.operations.push(fill_color(color)); // Color
+    if render_mode.strokes() {
+        content.operations.push(stroke_color(color)); // Outline color
+        content.operations.push(Operation::new(
+            "w",
+            vec![(layout.font_size * OUTLINE_WIDTH).into()],
+        )); // Outline width
+    }
     content.operations.push(Operation::new("BT", vec![])); // Begin text
-    content.operations.push(Operation::new(
-        "Tf",
-        vec![
-            Object::Name(font_name.as_bytes().to_vec()),
-            options.font_size.into(),
-        ],
-    )); // Font size and face
-
-    let baseline_y = page_h - options.y - options.font_size * 0.2;
-
-    content.operations.push(Operation::new(
-        "Tm",
-        vec![
-            1.into(),
-            0.into(),
-            0.into(),
-            1.into(),
-            options.x.into(),
-            baseline_y.into(),
-        ],
-    )); // Move text position
-
-    // Render each glyph with correct advance
-
-    if let FontType::Cursive = font_type {
-        let mut chars = options.text.chars();
-        for pos in glyph_buffer.glyph_positions() {
-            let text = format!("{}", chars.next().unwrap_or(' ')); // placeholder for actual mapping
-            content
-                .operations
-                .push(Operation::new("Tj", vec![Object::string_literal(text)]));
-            if pos.x_advance != 0 {
-                let adv = pos.x_advance as f32 * scale - options.font_size * scale * 0.75;
+    // Always set, text state carries over from the page's other content streams
+    content
+        .operations
+        .push(Operation::new("Tc", vec![options.spacing.letter.into()])); // Letter spacing
+    content
+        .operations
+        .push(Operation::new("Tr", vec![render_mode.operand().into()])); // Rendering mode
+
+    let mut current_font: Option<(&str, f32)> = None;
+    let mut current_color = color;
+    // Strokes of each line follow its largest run, like its ascent and descent
+    let mut line_metrics = vec![];
+    for line in &layout.lines {
+        let mut largest: Option<(&Face, f32)> = None;
+        content.operations.push(Operation::new(
+            "Tm",
+            vec![
+                1.into(),
+                0.into(),
+                0.into(),
+                1.into(),
+                line.x.into(),
+                (frame.h - line.baseline).into(),
+            ],
+        )); // Move text position
 
-                content
-                    .operations
-                    .push(Operation::new("Td", vec![adv.into(), 0.into()]));
+        let segment_fonts: Vec<(usize, PdfFont)> = line
+            .segments
+            .iter()
+            .map(|(offset, style)| (*offset, style.font))
+            .collect();
+
+        let runs =
+            shape_text_segments(&line.text, &segment_fonts, options.features, Some(line.rtl));
+        for run in runs {
+            let style = style_of(&line.segments, run.offset);
+            let font_size = layout.font_size * style.scale;
+            if largest.is_none_or(|(_, size)| font_size > size) {
+                largest = Some((run.font.face, font_size));
+            }
+            if current_font != Some((run.font.name, font_size)) {
+                content.operations.push(Operation::new(
+                    "Tf",
+                    vec![
+                        Object::Name(run.font.name.as_bytes().to_vec()),
+                        font_size.into(),
+                    ],
+                )); // Font size and face
+                current_font = Some((run.font.name, font_size));
+            }
+
+            let run_color = style.color.unwrap_or(color);
+            if run_color != current_color {
+                content.operations.push(fill_color(run_color)); // Span color
+                if render_mode.strokes() {
+                    content.operations.push(stroke_color(run_color));
+                }
+                current_color = run_color;
             }
+
+            fonts.record_glyphs(
+                run.font.name,
+                run.font.face,
+                glyph_texts(&run.text, &run.glyph_buffer),
+            );
+
+            // Write the shaped run rather than the plain string so kerning, ligatures and mark
+            // positioning end up on the page and rendered widths match `text_width`
+            content.operations.extend(glyph_run_operations(
+                run.font.face,
+                &run.text,
+                &run.glyph_buffer,
+                font_size,
+                Spacing {
+                    letter: options.spacing.letter,
+                    word: line.word_spacing,
+                },
+            ));
         }
-    } else {
-        content.operations.push(Operation::new(
-            "Tj",
-            vec![Object::string_literal(options.text)],
-        ));
+
+        let (face, font_size) = largest.unwrap_or((font.face, layout.font_size));
+        line_metrics.push(DecorationMetrics::new(face, font_size));
     }
 
     content.operations.push(
         Operation::new("ET", vec![]), // End text
     );
+    if current_color != color {
+        content.operations.push(fill_color(color));
+    }
+
+    // Strokes as filled rectangles, centred on the positions the face gives
+    let strokes = [
+        (decoration.underline, false),
+        (decoration.strikethrough, true),
+    ];
+    for (_, strikethrough) in strokes.into_iter().filter(|(enabled, _)| *enabled) {
+        for (line, metrics) in layout
+            .lines
+            .iter()
+            .zip(&line_metrics)
+            .filter(|(line, _)| line.width > 0.0)
+        {
+            let (position, thickness) = if strikethrough {
+                metrics.strikethrough
+            } else {
+                metrics.underline
+            };
+            let y = frame.h - line.baseline + position - thickness / 2.0;
+            content
+                .operations
+                .push(rectangle(line.x, y, line.width, thickness));
+        }
+        content.operations.push(Operation::new("f", vec![]));
+    }
+    content.operations.push(Operation::new("Q", vec![])); // End of clipping
+
+    // Drawn outside the clip so the whole line shows
+    if let Some(border) = decoration.border {
+        content
+            .operations
+            .push(stroke_color(border.color.unwrap_or(color))); // Border color
+        content.operations.push(Operation::new(
+            "w",
+            vec![border.width.unwrap_or(1.0).into()],
+        )); // Line width
+        content
+            .operations
+            .push(rectangle(0.0, 0.0, frame.w, frame.h));
+        content.operations.push(Operation::new("S", vec![])); // Stroke
+    }
+    content.operations.push(Operation::new("Q", vec![])); // Restore graphics state
 
     let encoded_content = content.encode()?;
 
@@ -141,241 +691,579 @@ pub fn draw_text<'a>(
     Ok(())
 }
 
-pub fn draw_text_wrap<'a>(
+// Distance from `y` down to the first baseline of `draw_text`, as a fraction of the font size
+const DRAW_TEXT_BASELINE: f32 = 0.2;
+
+// Draw text at its font size without wrapping, only explicit newlines start new lines. Unlike
+// the other functions the box only gives the alignment: the first baseline sits a fifth of the
+// font size below `y`, where `draw_text` has always put it, and text is drawn past the edges of
+// the box.
+pub fn draw_text<'a>(
     document: &'a mut Document,
+    fonts: &mut FontRegistry,
     page: &(u32, u16),
     options: DrawTextOptions<'a>,
 ) -> Result<(), BoxedError> {
-    let font_weight = options.font_weight.unwrap_or(FontWeight::Regular);
-    let font_type = options.font_type.unwrap_or(FontType::Serif);
-    let font_face = get_font(&font_type, &font_weight, false);
-
-    let estimated_chars_per_line =
-        ((options.w / options.font_size.max(1.0)) * 1.2).clamp(5.0, 120.0) as usize;
-    let pre_wrapped = hard_wrap_by_chars(options.text, estimated_chars_per_line);
-
-    let (text, line_count, _) = wrap_text(
-        font_face, // Use the font_face
-        &pre_wrapped,
-        options.font_size,
-        options.w,
-    );
-
-    let line_height = options.font_size * 1.2;
+    let layout_options = options.layout_options(options.font_size, false, Overflow::Visible);
+    let mut layout = layout_text(options.text, &layout_options);
 
-    let total_text_height = (line_count as f32) * line_height;
-    let y_offset = match options.align_v.unwrap_or(VerticalAlign::Top) {
-        VerticalAlign::Top => 0.0,
-        VerticalAlign::Middle => (options.h - total_text_height) / 2.0,
-        VerticalAlign::Bottom => options.h - total_text_height,
-    };
-
-    for (index, line) in text.split('\n').enumerate() {
-        let line_y = options.y + y_offset + (index as f32) * line_height;
-        // --- End Fix ---
+    let shift = DRAW_TEXT_BASELINE * layout.font_size
+        - layout.lines.first().map_or(0.0, |line| line.baseline);
+    for line in &mut layout.lines {
+        line.baseline += shift;
+    }
 
-        let width = text_width(font_face, line, options.font_size);
-        let line_x = match options.text_alignment {
-            Some(TextAlignment::Center) => options.x + (options.w - width) / 2.0,
-            Some(TextAlignment::Right) => options.x + (options.w - width),
-            _ => options.x,
-        };
+    draw_layout(document, fonts, page, &options, &layout, false)
+}
 
-        draw_text(
-            document,
-            page,
-            DrawTextOptions {
-                text: line,
-                font_size: options.font_size,
-                font_weight: options.font_weight,
-                font_type: options.font_type,
-                text_alignment: None,
-                align_v: None,
-                color: options.color,
-                x: line_x,
-                y: line_y,
-                w: options.w,
-                h: line_height, // Use line_height, not original box height
-            },
-        )?;
-    }
-    Ok(())
+// Draw text at its font size, wrapped to the width of the box. By default text overflowing the
+// box is drawn past its edges.
+pub fn draw_text_wrap<'a>(
+    document: &'a mut Document,
+    fonts: &mut FontRegistry,
+    page: &(u32, u16),
+    options: DrawTextOptions<'a>,
+) -> Result<(), BoxedError> {
+    let layout_options = options.layout_options(options.font_size, true, Overflow::Visible);
+    draw_text_box(document, fonts, page, &options, layout_options)
 }
 
+// Draw wrapped text at the largest size that fits the box, up to the box height. With another
+// overflow policy set the text is drawn at its font size instead.
 pub fn draw_text_fit<'a>(
     document: &'a mut Document,
+    fonts: &mut FontRegistry,
     page: &(u32, u16),
     options: DrawTextOptions<'a>,
 ) -> Result<(), BoxedError> {
-    let font_weight = options.font_weight.unwrap_or(FontWeight::Regular);
-    let font_type = options.font_type.unwrap_or(FontType::Serif);
-    let font_face = get_font(&font_type, &font_weight, false);
-
-    let pre_wrapped_text = hard_wrap_by_chars(options.text, 25);
+    let font_size = match options.overflow.unwrap_or(Overflow::Shrink) {
+        Overflow::Shrink => options.frame_size().1,
+        _ => options.font_size,
+    };
+    let layout_options = options.layout_options(font_size, true, Overflow::Shrink);
+    draw_text_box(document, fonts, page, &options, layout_options)
+}
 
-    // Find the best font size that fits
-    let (text, font_size) =
-        fit_text_font_size(font_face, &pre_wrapped_text, options.w, options.h).ok_or(
-            GenericError(format!("Unable to fit text: {}", options.text)),
-        )?;
+// Draw wrapped text at its font size, shrunk until it fits the box unless another overflow policy
+// is set
+pub fn draw_text_wrap_fit<'a>(
+    document: &'a mut Document,
+    fonts: &mut FontRegistry,
+    page: &(u32, u16),
+    options: DrawTextOptions<'a>,
+) -> Result<(), BoxedError> {
+    let layout_options = options.layout_options(options.font_size, true, Overflow::Shrink);
+    draw_text_box(document, fonts, page, &options, layout_options)
+}
 
-    let line_height = font_size * 1.2;
-    let lines: Vec<&str> = text.split('\n').collect(); // Get lines
-    let line_count = lines.len(); // Get line_count
+// Draw text at its font size on its own lines, only explicit newlines start new lines. The text is
+// shrunk until its longest line fits the box unless another overflow policy is set.
+pub fn draw_text_lines_fit<'a>(
+    document: &'a mut Document,
+    fonts: &mut FontRegistry,
+    page: &(u32, u16),
+    options: DrawTextOptions<'a>,
+) -> Result<(), BoxedError> {
+    let layout_options = options.layout_options(options.font_size, false, Overflow::Shrink);
+    draw_text_box(document, fonts, page, &options, layout_options)
+}
 
-    let total_text_height = (line_count as f32) * line_height;
+// Draw a Markdown document in the box of `options`. By default the document is shrunk until it
+// fits.
+pub fn draw_markdown(
+    document: &mut Document,
+    fonts: &mut FontRegistry,
+    page: &(u32, u16),
+    options: DrawTextOptions,
+    blocks: &[MarkdownBlock],
+) -> Result<(), BoxedError> {
+    let layout = layout_markdown(blocks, &options);
+    let overflow = options.overflow.unwrap_or(Overflow::Shrink);
+    if layout.overflows && matches!(overflow, Overflow::Fail) {
+        return Err(overflow_error(&options, &layout).into());
+    }
 
-    // Correction to align baseline properly with draw_text
-    let correction_bottom = font_size * 1.0;
-    let correction_middle = font_size * 0.6;
-    let correction_top = font_size * 0.25;
+    let clip = matches!(overflow, Overflow::Clip);
+    draw_layout(document, fonts, page, &options, &layout, clip)
+}
 
-    let y_offset = match options.align_v.unwrap_or(VerticalAlign::Top) {
-        VerticalAlign::Top => correction_top,
-        VerticalAlign::Middle => (options.h - total_text_height) / 2.0 + correction_middle,
-        VerticalAlign::Bottom => options.h - total_text_height + correction_bottom,
-    };
+// A box on a page, in the same coordinates as `DrawTextOptions`
+#[derive(Debug, Clone, Copy)]
+pub struct TextBox {
+    pub page: (u32, u16),
+    pub x: f32,
+    pub y: f32,
+    pub w: f32,
+    pub h: f32,
+}
 
-    for (index, line) in lines.into_iter().enumerate() {
-        let line_y = options.y + y_offset + (index as f32) * line_height;
+// Page copied for text left over after the last box, with the content the template page had before
+// anything was drawn on it
+#[derive(Debug, Clone)]
+pub struct OverflowTemplate<'a> {
+    pub text_box: TextBox,
+    pub contents: Option<&'a Object>,
+}
 
-        let width = text_width(font_face, line, font_size);
-        let aligned_x = match options.text_alignment.clone() {
-            Some(TextAlignment::Center) => options.x + (options.w - width) / 2f32,
-            Some(TextAlignment::Right) => options.x + (options.w - width),
-            _ => options.x,
-        };
+// Draw the lines of the text that fit the box of `options` at its font size. Returns where the
+// text left over starts.
+fn draw_text_part(
+    document: &mut Document,
+    fonts: &mut FontRegistry,
+    page: &(u32, u16),
+    options: &DrawTextOptions,
+) -> Result<usize, BoxedError> {
+    let layout_options = options.layout_options(options.font_size, true, Overflow::Visible);
+    let (layout, rest) = layout_text_part(options.text, &layout_options);
+    draw_layout(document, fonts, page, options, &layout, false)?;
 
-        draw_text(
-            document,
-            page,
-            DrawTextOptions {
-                text: line,
-                font_size,
-                font_weight: options.font_weight,
-                font_type: options.font_type,
-                text_alignment: None,
-                align_v: None,
-                color: options.color,
-                x: aligned_x,
-                y: line_y,
-                w: options.w,
-                h: line_height,
-            },
-        )?;
-    }
-    Ok(())
+    Ok(rest)
 }
 
-pub fn draw_text_wrap_fit<'a>(
-    document: &'a mut Document,
+// Most copies of an overflow page a single text appends to the document
+pub const MAX_OVERFLOW_PAGES: usize = 50;
+
+// Draw wrapped text at its font size, flowing the lines that do not fit the box of `options` into
+// the continuation boxes in order. With an overflow page, text left over after the last box goes
+// to copies of that page appended to the document, as many as needed up to `MAX_OVERFLOW_PAGES`.
+// Without one, the last box takes the rest of the text with the overflow policy of `options`,
+// shrinking it by default.
+pub fn draw_text_flow(
+    document: &mut Document,
+    fonts: &mut FontRegistry,
     page: &(u32, u16),
-    opts: DrawTextOptions<'a>,
+    options: DrawTextOptions,
+    continuations: &[TextBox],
+    overflow_page: Option<OverflowTemplate>,
 ) -> Result<(), BoxedError> {
-    let DrawTextOptions {
-        text,
-        mut font_size,
-        font_weight,
-        font_type,
-        text_alignment,
-        align_v,
-        color,
-        x,
-        y,
-        w,
-        h,
-    } = opts;
-
-    let font_face = get_font(
-        &font_type.clone().unwrap_or(FontType::Serif),
-        &font_weight.clone().unwrap_or(FontWeight::Regular),
-        false,
-    );
+    if options.comb.is_some() {
+        return Err(InvalidOptionError {
+            field: options.field.to_string(),
+            message: "A comb cannot continue in other boxes".into(),
+        }
+        .into());
+    }
 
-    let words: Vec<&str> = text.split_whitespace().collect();
-    let mut lines: Vec<String> = Vec::new();
+    let first = TextBox {
+        page: *page,
+        x: options.x,
+        y: options.y,
+        w: options.w,
+        h: options.h,
+    };
+    let boxes = std::iter::once(first).chain(continuations.iter().copied());
+    let last_box = continuations.len();
 
-    //dynamic shrinking
-    loop {
-        lines.clear();
-        let mut current_line = String::new();
+    // Where the text left for the next box starts
+    let mut start = 0;
+    for (index, text_box) in boxes.enumerate() {
+        let styles = skip_spans(options.styles, start);
+        let box_options = DrawTextOptions {
+            text: &options.text[start..],
+            styles: &styles,
+            x: text_box.x,
+            y: text_box.y,
+            w: text_box.w,
+            h: text_box.h,
+            ..options.clone()
+        };
 
-        for word in &words {
-            let test_line = if current_line.is_empty() {
-                word.to_string()
-            } else {
-                format!("{} {}", current_line, word)
-            };
+        if index == last_box && overflow_page.is_none() {
+            let layout_options =
+                box_options.layout_options(options.font_size, true, Overflow::Shrink);
+            return draw_text_box(
+                document,
+                fonts,
+                &text_box.page,
+                &box_options,
+                layout_options,
+            );
+        }
 
-            if text_width(&font_face, &test_line, font_size) <= w {
-                current_line = test_line;
-            } else {
-                if !current_line.is_empty() {
-                    lines.push(current_line);
+        start += draw_text_part(document, fonts, &text_box.page, &box_options)?;
+        if options.text[start..].trim().is_empty() {
+            return Ok(());
+        }
+    }
+
+    if let Some(overflow_page) = overflow_page {
+        let mut page_count = 0;
+        while !options.text[start..].trim().is_empty() {
+            if page_count == MAX_OVERFLOW_PAGES {
+                return Err(OverflowPageLimitError {
+                    field: options.field.to_string(),
+                    max_pages: MAX_OVERFLOW_PAGES,
                 }
-                current_line = word.to_string();
+                .into());
             }
+            page_count += 1;
+
+            let text_box = overflow_page.text_box;
+            let page = append_page_copy(document, &text_box.page, overflow_page.contents)?;
+            let styles = skip_spans(options.styles, start);
+            let box_options = DrawTextOptions {
+                text: &options.text[start..],
+                styles: &styles,
+                x: text_box.x,
+                y: text_box.y,
+                w: text_box.w,
+                h: text_box.h,
+                ..options.clone()
+            };
+            start += draw_text_part(document, fonts, &page, &box_options)?;
         }
-        if !current_line.is_empty() {
-            lines.push(current_line);
-        }
+    }
+
+    Ok(())
+}
 
-        let total_height = lines.len() as f32 * font_size;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::dictionary;
+    use rustybuzz::UnicodeBuffer;
+
+    fn shape(face: &Face, text: &str) -> GlyphBuffer {
+        let mut buffer = UnicodeBuffer::new();
+        buffer.push_str(text);
+        rustybuzz::shape(face, &[], buffer)
+    }
 
-        let fits_width = lines
+    fn sans() -> &'static Face<'static> {
+        get_font(&FontType::SansSerif, &FontWeight::Regular, false).face
+    }
+
+    #[test]
+    fn glyph_texts_cover_the_shaped_text() {
+        let text = "office, naïve";
+        let glyph_buffer = shape(sans(), text);
+
+        let texts = glyph_texts(text, &glyph_buffer);
+
+        assert_eq!(texts.len(), glyph_buffer.len());
+        assert_eq!(
+            texts
+                .iter()
+                .map(|(_, text)| text.as_str())
+                .collect::<String>(),
+            text
+        );
+        assert!(texts.iter().all(|(glyph_id, _)| *glyph_id != 0));
+    }
+
+    #[test]
+    fn glyph_texts_give_ligatures_all_their_characters() {
+        let text = "fi";
+        let glyph_buffer = shape(sans(), text);
+
+        let texts = glyph_texts(text, &glyph_buffer);
+
+        assert_eq!(texts.len(), 1);
+        assert_eq!(texts[0].1, "fi");
+    }
+
+    fn adjustments(operations: &[Operation]) -> Vec<f32> {
+        operations
             .iter()
-            .all(|l| text_width(&font_face, l, font_size) <= w);
-        let fits_height = total_height <= h;
+            .filter(|operation| operation.operator == "TJ")
+            .flat_map(|operation| operation.operands[0].as_array().unwrap())
+            .filter_map(|object| object.as_float().ok())
+            .collect()
+    }
 
-        if fits_width && fits_height {
-            break;
-        }
+    #[test]
+    fn glyph_runs_follow_the_default_advances() {
+        let text = "plain text";
+        let glyph_buffer = shape(sans(), text);
+
+        let operations =
+            glyph_run_operations(sans(), text, &glyph_buffer, 10.0, Spacing::default());
+
+        assert_eq!(operations.len(), 1);
+        assert!(adjustments(&operations).is_empty());
+    }
+
+    #[test]
+    fn word_spacing_is_written_as_adjustments() {
+        let text = "a b";
+        let glyph_buffer = shape(sans(), text);
+        let spacing = Spacing {
+            letter: 0.0,
+            word: 5.0,
+        };
 
-        font_size -= 0.5; // shrink gradually
-        if font_size <= 1.0 {
-            break; // minimum font size
+        let operations = glyph_run_operations(sans(), text, &glyph_buffer, 10.0, spacing);
+
+        // Half an em at 10 points, in thousandths of an em
+        assert_eq!(adjustments(&operations), [-500.0]);
+    }
+
+    // Document with a single 200 by 100 point page
+    fn document_with_page() -> (Document, (u32, u16)) {
+        let mut document = Document::with_version("1.7");
+        let pages_id = document.new_object_id();
+        let page_id = document.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => vec![0.into(), 0.into(), 200.into(), 100.into()],
+        });
+        document.objects.insert(
+            pages_id,
+            dictionary! {
+                "Type" => "Pages",
+                "Kids" => vec![page_id.into()],
+                "Count" => 1,
+            }
+            .into(),
+        );
+        let catalog_id = document.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+        });
+        document.trailer.set("Root", catalog_id);
+
+        (document, page_id)
+    }
+
+    // Options drawing the text at 10 points in a 100 by 30 point box
+    fn text_options(text: &str) -> DrawTextOptions<'_> {
+        DrawTextOptions {
+            field: "notes",
+            text,
+            font_size: 10.0,
+            font_type: Some(FontType::SansSerif),
+            x: 10.0,
+            y: 10.0,
+            w: 100.0,
+            h: 30.0,
+            ..Default::default()
         }
     }
 
-    let line_height = font_size;
-    let total_height = lines.len() as f32 * line_height;
+    fn operators(document: &Document, page: (u32, u16)) -> Vec<String> {
+        document
+            .get_and_decode_page_content(page)
+            .unwrap()
+            .operations
+            .into_iter()
+            .map(|operation| operation.operator)
+            .collect()
+    }
+
+    const LONG_TEXT: &str = "the quick brown fox jumps over the lazy dog and runs far away \
+                             across the fields until the sun goes down behind the hills";
 
-    // --- Vertical alignment ---
-    let start_y = match align_v.unwrap_or(VerticalAlign::Top) {
-        VerticalAlign::Top => y + (line_height * 0.9), //3.0, //+offset
-        VerticalAlign::Middle => y + (h - total_height) / 2.0 + (line_height * 0.75),
-        VerticalAlign::Bottom => y + h - total_height + (line_height * 0.75),
-    };
+    #[test]
+    fn fail_reports_text_that_does_not_fit() {
+        let (mut document, page) = document_with_page();
+        let options = DrawTextOptions {
+            overflow: Some(Overflow::Fail),
+            ..text_options(LONG_TEXT)
+        };
+
+        let err = draw_text_wrap_fit(&mut document, &mut FontRegistry::new(), &page, options)
+            .unwrap_err();
 
-    let mut current_y = start_y;
-    for line in &lines {
-        let line_width = text_width(&font_face, line, font_size);
-        let x_offset = match text_alignment.as_ref().unwrap_or(&TextAlignment::Left) {
-            TextAlignment::Left => 0.0,
-            TextAlignment::Center => (w - line_width) / 2.0,
-            TextAlignment::Right => w - line_width,
+        let err = err.downcast_ref::<TextOverflowError>().unwrap();
+        assert_eq!(err.field, "notes");
+        assert!(err.text_height > err.box_height);
+        // Nothing is drawn
+        assert!(operators(&document, page).is_empty());
+    }
+
+    #[test]
+    fn clip_cuts_text_off_at_the_box() {
+        let (mut document, page) = document_with_page();
+        let options = DrawTextOptions {
+            overflow: Some(Overflow::Clip),
+            ..text_options(LONG_TEXT)
         };
 
-        draw_text(
-            document,
+        draw_text_wrap_fit(&mut document, &mut FontRegistry::new(), &page, options).unwrap();
+
+        let operators = operators(&document, page);
+        let position = |name: &str| operators.iter().position(|operator| operator == name);
+        let clip = position("W").unwrap();
+        assert_eq!(operators[clip - 1], "re");
+        assert_eq!(operators[clip + 1], "n");
+        assert!(clip < position("BT").unwrap());
+    }
+
+    #[test]
+    fn text_flows_into_the_continuation_boxes() {
+        let (mut document, page) = document_with_page();
+        let continuation = TextBox {
             page,
-            DrawTextOptions {
-                text: &line,
-                font_size,
-                font_weight,
-                font_type: font_type.clone(),
-                text_alignment: None,
-                align_v: None,
-                color,
-                x: x + x_offset,
-                y: current_y,
-                w,
-                h: line_height,
+            x: 110.0,
+            y: 0.0,
+            w: 85.0,
+            h: 100.0,
+        };
+
+        draw_text_flow(
+            &mut document,
+            &mut FontRegistry::new(),
+            &page,
+            text_options(LONG_TEXT),
+            &[continuation],
+            None,
+        )
+        .unwrap();
+
+        let operations = document
+            .get_and_decode_page_content(page)
+            .unwrap()
+            .operations;
+        let texts = operations
+            .iter()
+            .filter(|operation| operation.operator == "BT");
+        assert_eq!(texts.count(), 2);
+        // Both boxes keep the font size, the rest of the text fits the second one
+        assert!(
+            operations
+                .iter()
+                .filter(|operation| operation.operator == "Tf")
+                .all(|operation| operation.operands[1].as_float().unwrap() == 10.0)
+        );
+    }
+
+    // The text box of the page as the box of copies of the page
+    fn overflow_template(page: (u32, u16)) -> OverflowTemplate<'static> {
+        OverflowTemplate {
+            text_box: TextBox {
+                page,
+                x: 10.0,
+                y: 10.0,
+                w: 100.0,
+                h: 30.0,
             },
-        )?;
+            contents: None,
+        }
+    }
+
+    #[test]
+    fn overflow_pages_take_the_text_left_after_the_last_box() {
+        let (mut document, page) = document_with_page();
+
+        draw_text_flow(
+            &mut document,
+            &mut FontRegistry::new(),
+            &page,
+            text_options(LONG_TEXT),
+            &[],
+            Some(overflow_template(page)),
+        )
+        .unwrap();
 
-        current_y += line_height; // move down for next line
+        let pages = document.get_pages();
+        assert!(pages.len() > 2);
+        assert!(
+            pages
+                .values()
+                .all(|page| operators(&document, *page).contains(&"BT".to_string()))
+        );
     }
 
-    Ok(())
+    #[test]
+    fn overflow_pages_stop_at_the_limit() {
+        let (mut document, page) = document_with_page();
+        // Two lines fit each box
+        let text = "line\n".repeat(2 * MAX_OVERFLOW_PAGES + 10);
+
+        let err = draw_text_flow(
+            &mut document,
+            &mut FontRegistry::new(),
+            &page,
+            text_options(&text),
+            &[],
+            Some(overflow_template(page)),
+        )
+        .unwrap_err();
+
+        let err = err.downcast_ref::<OverflowPageLimitError>().unwrap();
+        assert_eq!(err.field, "notes");
+        assert_eq!(err.max_pages, MAX_OVERFLOW_PAGES);
+        assert_eq!(document.get_pages().len(), MAX_OVERFLOW_PAGES + 1);
+    }
+
+    #[test]
+    fn underlines_and_strikethroughs_are_filled_along_every_line() {
+        let (mut document, page) = document_with_page();
+        let options = DrawTextOptions {
+            decoration: TextDecoration {
+                underline: true,
+                strikethrough: true,
+                ..Default::default()
+            },
+            ..text_options("one\ntwo")
+        };
+
+        draw_text_wrap(&mut document, &mut FontRegistry::new(), &page, options).unwrap();
+
+        // After the text, one fill of a rectangle per line for each kind of stroke
+        let operations = document
+            .get_and_decode_page_content(page)
+            .unwrap()
+            .operations;
+        let text_end = operations
+            .iter()
+            .position(|operation| operation.operator == "ET");
+        let strokes: Vec<Vec<[f32; 4]>> = operations[text_end.unwrap()..]
+            .split(|operation| operation.operator == "f")
+            .map(|group| {
+                group
+                    .iter()
+                    .filter(|operation| operation.operator == "re")
+                    .map(|operation| {
+                        let operand = |index: usize| operation.operands[index].as_float().unwrap();
+                        [operand(0), operand(1), operand(2), operand(3)]
+                    })
+                    .collect()
+            })
+            .filter(|rectangles: &Vec<[f32; 4]>| !rectangles.is_empty())
+            .collect();
+
+        let [underlines, strikethroughs] = strokes.as_slice() else {
+            panic!("Strokes {strokes:?}");
+        };
+        assert_eq!(underlines.len(), 2);
+        assert_eq!(strikethroughs.len(), 2);
+        for (underline, strikethrough) in underlines.iter().zip(strikethroughs) {
+            assert!(underline[2] > 0.0 && underline[3] > 0.0);
+            assert_eq!(underline[2], strikethrough[2]);
+            assert!(strikethrough[1] > underline[1]);
+        }
+        // The second line is below the first
+        assert!(underlines[1][1] < underlines[0][1]);
+    }
+
+    #[test]
+    fn texts_with_the_same_opacity_share_a_graphics_state() {
+        let (mut document, page) = document_with_page();
+        let mut fonts = FontRegistry::new();
+
+        for opacity in [0.5, 0.25, 0.5] {
+            let options = DrawTextOptions {
+                opacity: Some(opacity),
+                ..text_options("faded")
+            };
+            draw_text_wrap(&mut document, &mut fonts, &page, options).unwrap();
+        }
+
+        let resources = document.get_dictionary(page).unwrap().get(b"Resources");
+        let states = resources.unwrap().as_dict().unwrap().get(b"ExtGState");
+        let states = states.unwrap().as_dict().unwrap();
+        assert_eq!(states.len(), 2);
+        let half = states
+            .get(b"pdf-Opacity500")
+            .unwrap()
+            .as_reference()
+            .unwrap();
+        let half = document.get_dictionary(half).unwrap();
+        assert_eq!(half.get(b"ca").unwrap().as_float().unwrap(), 0.5);
+        assert_eq!(half.get(b"CA").unwrap().as_float().unwrap(), 0.5);
+        let operators = operators(&document, page);
+        let selected = operators.iter().filter(|operator| *operator == "gs");
+        assert_eq!(selected.count(), 3);
+    }
 }